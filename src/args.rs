@@ -1,11 +1,17 @@
-use clap::Parser;
-use std::sync::LazyLock;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
-pub static ARGS: LazyLock<Args> = LazyLock::new(Args::parse);
+use crate::logging::LogFormat;
+use crate::package::formats::Format;
+use crate::schema::SchemaKind;
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Default)]
 #[command(version, about)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// The packages to check
     pub packages: Vec<String>,
 
@@ -20,4 +26,121 @@ pub struct Args {
     /// Do not use the cache
     #[arg(short = 'c', long)]
     pub no_cache: bool,
+
+    /// Aggregate formats to write, instead of those configured
+    #[arg(long = "format", value_enum, value_delimiter = ',')]
+    pub formats: Vec<Format>,
+
+    /// Keep files for channels that were removed or disabled
+    #[arg(long)]
+    pub keep_stale: bool,
+
+    /// How many packages to fetch at once [default: 64]
+    #[arg(short, long, env = "VAGRANT_JOBS", value_name = "N")]
+    pub jobs: Option<usize>,
+
+    /// Exit with 4 if more than this share of packages fail, rather than 3
+    #[arg(long, value_name = "RATE", value_parser = rate)]
+    pub max_failure_rate: Option<f64>,
+
+    /// Exit with 4 if any of these packages fail, rather than 3
+    #[arg(long, value_name = "PACKAGE", value_delimiter = ',')]
+    pub fail_on: Vec<String>,
+
+    /// Record the output of every fetch command to a directory
+    #[arg(long, value_name = "DIR", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Replay recorded output instead of executing fetch commands
+    #[arg(long, value_name = "DIR")]
+    pub replay: Option<PathBuf>,
+
+    /// The Vagrant root, containing p/ [default: working directory]
+    #[arg(long, env = "VAGRANT_ROOT", value_name = "DIR", global = true)]
+    pub root: Option<PathBuf>,
+
+    /// The cache directory [default: <root>/.vagrant-cache]
+    #[arg(long, env = "VAGRANT_CACHE", value_name = "DIR", global = true)]
+    pub cache_dir: Option<PathBuf>,
+
+    /// The shell library sourced by fetch commands [default: <root>/sh/lib.env]
+    #[arg(long, env = "SHLIB_PATH", value_name = "FILE", global = true)]
+    pub shlib: Option<PathBuf>,
+
+    /// The format of log output
+    #[arg(long, value_enum, default_value_t, global = true)]
+    pub log_format: LogFormat,
+
+    /// Also write logs to a file, without colors
+    #[arg(long, value_name = "FILE", global = true)]
+    pub log_file: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Inspect or manage the cache
+    #[command(subcommand)]
+    Cache(CacheCommand),
+
+    /// Commit the results of the last run to git
+    Commit {
+        /// Don't push the commits
+        #[arg(long)]
+        no_push: bool,
+    },
+
+    /// Check packages continuously, each on its own schedule
+    Daemon,
+
+    /// Render the database as a static site
+    Site {
+        /// The directory to render into [default: <root>/site]
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+
+        /// The absolute url the site will be served from, which feeds need for their ids and links
+        #[arg(long, value_name = "URL", value_parser = absolute_url)]
+        base_url: String,
+    },
+
+    /// Serve the database over a read-only HTTP API
+    Serve {
+        /// The address to listen on
+        #[arg(short, long, default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+
+    /// Print the JSON Schema for the JSON API
+    Schema {
+        #[arg(value_enum, default_value_t)]
+        kind: SchemaKind,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheCommand {
+    /// List cache entries
+    Ls,
+
+    /// Remove all cache entries
+    Clear,
+
+    /// Remove expired cache entries
+    Prune,
+}
+
+fn rate(s: &str) -> Result<f64, String> {
+    let rate = s.parse::<f64>().map_err(|e| e.to_string())?;
+    if (0.0..=1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(format!("{rate} is not between 0 and 1"))
+    }
+}
+
+fn absolute_url(s: &str) -> Result<String, String> {
+    match s.split_once("://") {
+        Some((scheme, rest)) if !scheme.is_empty() && !rest.is_empty() => Ok(s.to_string()),
+        _ => Err(format!("{s} is not an absolute url")),
+    }
 }
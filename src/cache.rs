@@ -0,0 +1,279 @@
+// cache.rs
+//
+// Upstream responses are cached by the shell library under .vagrant-cache/entries. Each entry is
+// a data file named <source>-<sha256 of url>, accompanied by a <key>.json metadata file. Entries
+// are staged to *.tmp files and renamed into place, so a partial download is never served.
+
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+use crate::context::Context;
+use crate::utils::duration;
+
+/// TTL used for sources without a configured TTL
+const DEFAULT_TTL: Duration = Duration::from_hours(1);
+
+/// The kinds of upstream data cached by the shell library
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheSource {
+    /// GitHub API responses (`_ghapi`)
+    Ghapi,
+    /// Remote refs (`_gitremote`)
+    Git,
+    /// Upstream pages (`cr`)
+    Curl,
+}
+
+impl CacheSource {
+    pub const ALL: [Self; 3] = [Self::Ghapi, Self::Git, Self::Curl];
+
+    /// The environment variable through which this source's TTL is passed to the shell library
+    #[must_use]
+    pub fn env_var(self) -> String {
+        format!("CACHE_TTL_{}", self.to_string().to_uppercase())
+    }
+}
+
+impl fmt::Display for CacheSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Ghapi => "ghapi",
+            Self::Git => "git",
+            Self::Curl => "curl",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Default TTL for all sources
+    #[serde(deserialize_with = "duration::deserialize")]
+    pub ttl: Duration,
+
+    /// Per-source TTL overrides
+    #[serde(deserialize_with = "duration::deserialize_map")]
+    pub sources: HashMap<CacheSource, Duration>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: DEFAULT_TTL,
+            sources: HashMap::new(),
+        }
+    }
+}
+
+impl CacheConfig {
+    #[must_use]
+    pub fn ttl(&self, source: CacheSource) -> Duration {
+        self.sources.get(&source).copied().unwrap_or(self.ttl)
+    }
+
+    /// TTLs in seconds for each source, keyed by environment variable
+    #[must_use]
+    pub fn env(&self) -> Vec<(String, String)> {
+        CacheSource::ALL
+            .iter()
+            .map(|&s| (s.env_var(), self.ttl(s).as_secs().to_string()))
+            .collect()
+    }
+}
+
+/// Metadata for a cache entry, as written by the shell library
+#[derive(Debug, Deserialize)]
+pub struct CacheEntry {
+    pub key: String,
+    pub source: String,
+    pub url: String,
+    /// Unix timestamp
+    pub fetched_at: u64,
+    /// Seconds
+    pub ttl: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl CacheEntry {
+    #[must_use]
+    pub const fn age(&self, now: u64) -> Duration {
+        Duration::from_secs(now.saturating_sub(self.fetched_at))
+    }
+
+    #[must_use]
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.age(now) >= Duration::from_secs(self.ttl)
+    }
+
+    fn data_path(&self, dir: &Path) -> PathBuf {
+        dir.join(&self.key)
+    }
+
+    fn meta_path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{}.json", self.key))
+    }
+}
+
+#[must_use]
+pub fn entries_dir(ctx: &Context) -> PathBuf {
+    ctx.cache.join("entries")
+}
+
+fn now() -> Result<u64> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .wrap_err("Time travel detected")?
+        .as_secs())
+}
+
+/// Create the cache directories if they don't exist
+///
+/// # Errors
+///
+/// Fails if the directories can't be created.
+pub fn init(ctx: &Context) -> Result<()> {
+    fs::create_dir_all(entries_dir(ctx)).wrap_err("Failed to create cache")
+}
+
+/// Read the metadata for all cache entries, sorted by key
+///
+/// Entries with missing data or unreadable metadata are skipped.
+///
+/// # Errors
+///
+/// Fails if the entries directory can't be read.
+pub fn entries(ctx: &Context) -> Result<Vec<CacheEntry>> {
+    let dir = entries_dir(ctx);
+    let mut entries = vec![];
+
+    for file in dir.read_dir()?.flatten() {
+        let path = file.path();
+        let name = file.file_name().to_string_lossy().to_string();
+        if name.contains(".tmp") || path.extension().is_none_or(|e| e != "json") {
+            continue;
+        }
+
+        let entry = match fs::read_to_string(&path)
+            .map_err(color_eyre::Report::from)
+            .and_then(|s| Ok(serde_json::from_str::<CacheEntry>(&s)?))
+        {
+            Ok(e) => e,
+            Err(e) => {
+                warn!("Ignoring invalid cache metadata {}: {e}", path.display());
+                continue;
+            }
+        };
+
+        if entry.data_path(&dir).is_file() {
+            entries.push(entry);
+        }
+    }
+
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(entries)
+}
+
+/// Remove expired entries, as well as stale temporary files and orphaned data or metadata
+///
+/// Returns the number of files removed.
+///
+/// # Errors
+///
+/// Fails if the cache can't be read or a file can't be removed.
+pub fn prune(ctx: &Context) -> Result<usize> {
+    init(ctx)?;
+    let dir = entries_dir(ctx);
+    let now = now()?;
+    let mut removed = 0;
+
+    for entry in entries(ctx)? {
+        if entry.is_expired(now) {
+            debug!("Pruning expired cache entry {}", entry.key);
+            fs::remove_file(entry.meta_path(&dir))?;
+            fs::remove_file(entry.data_path(&dir))?;
+            removed += 2;
+        }
+    }
+
+    for file in dir.read_dir()?.flatten() {
+        let path = file.path();
+        let name = file.file_name().to_string_lossy().to_string();
+
+        let stale = if name.contains(".tmp") {
+            true
+        } else if let Some(key) = name.strip_suffix(".json") {
+            !dir.join(key).is_file()
+        } else {
+            !dir.join(format!("{name}.json")).is_file()
+        };
+
+        if stale {
+            debug!("Pruning stale cache file {}", path.display());
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Remove all cache entries
+///
+/// Returns the number of files removed.
+///
+/// # Errors
+///
+/// Fails if the cache can't be read or removed.
+pub fn clear(ctx: &Context) -> Result<usize> {
+    init(ctx)?;
+    let removed = entries_dir(ctx).read_dir()?.count();
+    fs::remove_dir_all(entries_dir(ctx)).wrap_err("Failed to remove cache")?;
+    init(ctx)?;
+    Ok(removed)
+}
+
+/// Print a table of all cache entries
+///
+/// # Errors
+///
+/// Fails if the cache can't be read.
+pub fn ls(ctx: &Context) -> Result<()> {
+    init(ctx)?;
+    let now = now()?;
+
+    for entry in entries(ctx)? {
+        let status = if entry.is_expired(now) {
+            "expired"
+        } else {
+            "fresh"
+        };
+        let age = humantime::format_duration(entry.age(now)).to_string();
+        let ttl = humantime::format_duration(Duration::from_secs(entry.ttl)).to_string();
+
+        let key = entry.key.get(..16).unwrap_or(&entry.key);
+        let validator = if entry.etag.is_some() {
+            "etag"
+        } else if entry.last_modified.is_some() {
+            "mtime"
+        } else {
+            "-"
+        };
+
+        println!(
+            "{key:<18}{:<7}{age:<16}{ttl:<10}{status:<9}{validator:<7}{}",
+            entry.source, entry.url
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,321 @@
+// commit.rs
+//
+// Commits the results of a run to the database's git repository. Versions are diffed against those
+// at `HEAD`, then committed a channel at a time, followed by a commit for each package and a
+// summary commit carrying the run report from the cache.
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, bail};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::SystemTime;
+use tracing::{debug, info};
+
+use crate::context::Context;
+use crate::package::{VersionChange, bulk, parse_versions};
+
+/// Commit message templates, where `{name}` is replaced by the value of `name`
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommitConfig {
+    /// The first line of every commit body, with `{version}`, `{date}`, and `{runcount}`
+    pub header: String,
+    /// The subject for the runcount and log
+    pub aux: String,
+    /// The subject for a channel, with `{package}`, `{channel}`, `{old}`, `{new}`, and `{diff}`
+    pub channel: String,
+    /// The subject for a package, with `{package}`
+    pub package: String,
+    /// The subject for the summary
+    pub summary: String,
+}
+
+impl Default for CommitConfig {
+    fn default() -> Self {
+        Self {
+            header: "[ Vagrant v{version} | {date} | #{runcount} ]".into(),
+            aux: "auto(aux): update internal data".into(),
+            channel: "auto(p): {package}:{channel} | {diff}".into(),
+            package: "auto(p): update versions for {package}".into(),
+            summary: "auto(p): update versions".into(),
+        }
+    }
+}
+
+/// Fill in a template's placeholders
+#[must_use]
+pub fn render(template: &str, vars: &[(&str, &str)]) -> String {
+    vars.iter().fold(template.to_string(), |acc, (k, v)| {
+        acc.replace(&format!("{{{k}}}"), v)
+    })
+}
+
+struct Git<'a> {
+    root: &'a Path,
+}
+
+impl Git<'_> {
+    fn cmd(&self, args: &[&str]) -> Result<std::process::Output> {
+        Command::new("git")
+            .args(args)
+            .current_dir(self.root)
+            .output()
+            .wrap_err("Failed to run git")
+    }
+
+    fn run(&self, args: &[&str]) -> Result<String> {
+        let out = self.cmd(args)?;
+        if !out.status.success() {
+            bail!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+    }
+
+    /// Run a command whose failure doesn't matter
+    fn try_run(&self, args: &[&str]) {
+        if let Err(e) = self.run(args) {
+            debug!("{e}");
+        }
+    }
+
+    /// Commit whatever's staged, returning false if nothing was
+    fn commit(&self, subject: &str, body: &str) -> Result<bool> {
+        if self.cmd(&["diff", "--cached", "--quiet"])?.status.success() {
+            debug!("Nothing staged for '{subject}'");
+            return Ok(false);
+        }
+
+        self.run(&["commit", "-q", "-m", subject, "-m", body])?;
+        info!("Committed '{subject}'");
+        Ok(true)
+    }
+
+    /// A file's contents at `HEAD`, relative to the root
+    fn show(&self, path: &str) -> Option<String> {
+        self.run(&["show", &format!("HEAD:./{path}")]).ok()
+    }
+}
+
+/// Shorten commit hashes the way `git log --oneline` would
+fn short(version: &str) -> &str {
+    if version.len() == 40 && version.bytes().all(|b| b.is_ascii_hexdigit()) {
+        &version[..8]
+    } else {
+        version
+    }
+}
+
+fn diff(change: &VersionChange) -> String {
+    format!(
+        "{} -> {}",
+        change.old.as_deref().map_or("(none)", short),
+        short(&change.new)
+    )
+}
+
+/// A change as listed in package and summary commits
+fn line(change: &VersionChange) -> String {
+    format!(
+        "{:<40}{}",
+        format!("{}:{}", change.package, change.channel),
+        diff(change)
+    )
+}
+
+/// A run report from the cache, trimmed
+fn report(ctx: &Context, name: &str) -> String {
+    fs::read_to_string(ctx.cache.join(name)).map_or_else(|_| "?".into(), |s| s.trim().into())
+}
+
+/// A line-separated run report from the cache, as an indented list
+fn report_list(ctx: &Context, name: &str) -> (usize, String) {
+    let raw = fs::read_to_string(ctx.cache.join(name)).unwrap_or_default();
+    let lines = raw.lines().filter(|l| !l.is_empty()).collect::<Vec<_>>();
+    let list = lines
+        .iter()
+        .fold(String::new(), |acc, l| format!("{acc}    - {l}\n"));
+    (lines.len(), list)
+}
+
+/// The body of the summary commit, from the run report and the changes committed
+fn summary(ctx: &Context, header: &str, updated: &[VersionChange]) -> String {
+    let mut packages = updated.iter().map(|c| &c.package).collect::<Vec<_>>();
+    packages.dedup();
+    let packages = packages.len();
+
+    let count = |channel: &str| updated.iter().filter(|c| c.channel == channel).count();
+    let (release, unstable, commit) = (count("release"), count("unstable"), count("commit"));
+    let versions = updated
+        .iter()
+        .fold(String::new(), |acc, c| format!("{acc}    - {}\n", line(c)));
+    let (failed_count, failed) = report_list(ctx, "failed_channels");
+    let (pruned_count, pruned) = report_list(ctx, "pruned");
+    let (regressed_count, regressed) = report_list(ctx, "regressions");
+
+    format!(
+        "{header}
+
+- Completed in {elapsed}
+
+- Processed {total} packages:
+    - Checked   {checked}
+    - Skipped   {skipped}
+    - Failed    {failed_packages}
+
+- Updated {updated} versions for {packages} packages:
+    - Release   {release}
+    - Unstable  {unstable}
+    - Commit    {commit}
+    - Other     {other}
+
+- Updated versions:
+{versions}
+- Failed to fetch {failed_count} channels:
+{failed}
+- Refused {regressed_count} regressions:
+{regressed}
+- Pruned {pruned_count} stale channels:
+{pruned}",
+        elapsed = report(ctx, "elapsed"),
+        total = report(ctx, "total"),
+        checked = report(ctx, "checked"),
+        skipped = report(ctx, "skipped"),
+        failed_packages = report(ctx, "failed"),
+        updated = updated.len(),
+        other = updated.len() - release - unstable - commit,
+    )
+}
+
+fn stage_last_checked(git: &Git, ctx: &Context, dir: &str) -> Result<()> {
+    let path = format!("{dir}/last_checked");
+    if ctx.root.join(&path).exists() {
+        git.run(&["add", "--", &path])?;
+    }
+    Ok(())
+}
+
+/// Commit the database, returning the number of commits made
+///
+/// # Errors
+///
+/// Fails if a git command fails.
+pub fn commit(ctx: &Context, push: bool) -> Result<usize> {
+    let git = Git { root: &ctx.root };
+    git.run(&["rev-parse", "--git-dir"])
+        .wrap_err_with(|| format!("{} is not in a git repository", ctx.root.display()))?;
+
+    let templates = &ctx.config.commit;
+    let version = git.run(&["describe", "--tags"]).map_or_else(
+        |_| env!("CARGO_PKG_VERSION").to_string(),
+        |v| v.trim().into(),
+    );
+    let date = humantime::format_rfc3339_seconds(SystemTime::now())
+        .to_string()
+        .replace('T', " ")
+        .replace('Z', " +0000");
+    let runcount = fs::read_to_string(ctx.root.join("runcount"))
+        .map_or_else(|_| "?".into(), |s| s.trim().to_string());
+    let header = render(
+        &templates.header,
+        &[
+            ("version", &version),
+            ("date", &date),
+            ("runcount", &runcount),
+        ],
+    );
+
+    let mut commits = 0;
+
+    if ctx.root.join("runcount").exists() {
+        git.run(&["add", "runcount"])?;
+    }
+    if ctx.root.join("vagrant.log").exists() {
+        git.try_run(&["update-index", "--no-skip-worktree", "vagrant.log"]);
+        git.run(&["add", "vagrant.log"])?;
+    }
+    commits += usize::from(git.commit(&templates.aux, &header)?);
+
+    let mut updated = vec![];
+    let mut unchanged = vec![];
+    for package in bulk::find_all(ctx)? {
+        let Some(new) = package.read_previous_versions(ctx)? else {
+            continue;
+        };
+
+        let dir = format!("p/{}", package.name);
+        let old = git
+            .show(&format!("{dir}/versions.json"))
+            .map(|json| parse_versions(&json))
+            .transpose()
+            .wrap_err_with(|| format!("Invalid versions for '{}' at HEAD", package.name))?
+            .unwrap_or_default();
+
+        let changes = VersionChange::between(&package, &old, &new);
+        // pruned channels change the package without changing any versions
+        let pruned = !git
+            .run(&["status", "--porcelain", "--", &format!("{dir}/channels")])?
+            .is_empty();
+        if changes.is_empty() && !pruned {
+            unchanged.push(dir);
+            continue;
+        }
+
+        for change in &changes {
+            let diff = diff(change);
+            git.run(&["add", &format!("{dir}/channels/{}", change.channel)])?;
+            let subject = render(
+                &templates.channel,
+                &[
+                    ("package", &change.package),
+                    ("channel", &change.channel),
+                    ("old", change.old.as_deref().unwrap_or_default()),
+                    ("new", &change.new),
+                    ("diff", &diff),
+                ],
+            );
+            commits += usize::from(git.commit(&subject, &header)?);
+        }
+
+        git.run(&["add", "-A", "--", &format!("{dir}/channels")])?;
+        git.run(&["add", "-A", "--", &format!("{dir}/versions.*")])?;
+        stage_last_checked(&git, ctx, &dir)?;
+        let list = changes
+            .iter()
+            .fold(String::new(), |acc, c| format!("{acc} - {}\n", line(c)));
+        let subject = render(&templates.package, &[("package", &package.name)]);
+        commits += usize::from(git.commit(&subject, &format!("{header}\n\n{list}"))?);
+        updated.extend(changes);
+    }
+
+    let desc = summary(ctx, &header, &updated);
+
+    // metadata such as fetched_at changes every run, so it goes in with the summary
+    for dir in unchanged {
+        git.run(&["add", "-A", "--", &format!("{dir}/versions.*")])?;
+        stage_last_checked(&git, ctx, &dir)?;
+    }
+    for entry in ctx.packages_dir().read_dir()?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with("ALL.") || name.ends_with(".schema.json") {
+            git.run(&["add", "--", &format!("p/{name}")])?;
+        }
+    }
+    commits += usize::from(git.commit(&templates.summary, desc.trim_end())?);
+
+    if push {
+        git.run(&["push", "-q"])?;
+        info!("Pushed {commits} commits");
+    }
+
+    if ctx.root.join("vagrant.log").exists() {
+        git.try_run(&["update-index", "--skip-worktree", "vagrant.log"]);
+    }
+
+    Ok(commits)
+}
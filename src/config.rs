@@ -0,0 +1,84 @@
+// config.rs
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, bail};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::cache::CacheConfig;
+use crate::commit::CommitConfig;
+use crate::daemon::DaemonConfig;
+use crate::notify::NotifierConfig;
+use crate::package::SCHEMA_VERSION;
+use crate::package::formats::Format;
+use crate::package::throttle::HostLimit;
+
+/// Global configuration, read from `vagrant.toml` in the Vagrant root
+///
+/// Every field is optional, and the file itself may be absent.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub cache: CacheConfig,
+    pub output: OutputConfig,
+    /// Notifiers for version changes, as `[[notify]]` tables
+    pub notify: Vec<NotifierConfig>,
+    pub commit: CommitConfig,
+    pub daemon: DaemonConfig,
+    /// Limits on packages fetching from each upstream host, where `"*"` applies to unlisted hosts
+    pub hosts: HashMap<String, HostLimit>,
+}
+
+/// Configuration for the written database
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// The schema version to write `versions.json` and `ALL.json` with
+    pub schema: u32,
+    /// The aggregate formats to write, overridden by `--format`
+    pub formats: Vec<Format>,
+}
+
+/// The schema version written when `output.schema` isn't given
+pub const DEFAULT_SCHEMA_VERSION: u32 = 2;
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            // the current schema changes the shape of both files, so it's opted into
+            schema: DEFAULT_SCHEMA_VERSION,
+            formats: Format::DEFAULT.to_vec(),
+        }
+    }
+}
+
+impl Config {
+    /// Load `vagrant.toml` from `root`, or the defaults if there is none
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read or isn't a valid config.
+    pub fn load(root: &Path) -> Result<Self> {
+        let path = root.join("vagrant.toml");
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let raw = fs::read_to_string(&path)
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+        let config: Self = toml::from_str(&raw)
+            .wrap_err_with(|| format!("Invalid config in {}", path.display()))?;
+
+        if !(1..=SCHEMA_VERSION).contains(&config.output.schema) {
+            bail!(
+                "Invalid config in {}: Unknown schema version {}",
+                path.display(),
+                config.output.schema
+            );
+        }
+
+        Ok(config)
+    }
+}
@@ -0,0 +1,86 @@
+// context.rs
+
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use std::env;
+use std::path::{self, Path, PathBuf};
+
+use crate::args::Args;
+use crate::config::Config;
+use crate::package::engine::DEFAULT_JOBS;
+use crate::package::formats::Format;
+
+/// Everything a run depends on from its environment
+///
+/// This is threaded through instead of living in globals, so a run can be pointed at any package
+/// tree, as the tests do. All paths are absolute, since fetch commands run from package
+/// directories.
+#[derive(Debug)]
+pub struct Context {
+    /// The Vagrant root, containing `p/`
+    pub root: PathBuf,
+    /// The directory for cache entries and transient data
+    pub cache: PathBuf,
+    /// The shell library sourced by fetch commands
+    pub shlib: PathBuf,
+    pub args: Args,
+    pub config: Config,
+}
+
+impl Context {
+    /// Form a context from `--root`, `--cache-dir`, and `--shlib`, falling back to the working
+    /// directory and the locations within it
+    ///
+    /// # Errors
+    ///
+    /// Fails if the working directory can't be determined or the config can't be loaded.
+    pub fn new(args: Args) -> Result<Self> {
+        let root = match &args.root {
+            Some(dir) => absolute(dir)?,
+            None => env::current_dir().wrap_err("Couldn't get working directory")?,
+        };
+        let cache = match &args.cache_dir {
+            Some(dir) => absolute(dir)?,
+            None => root.join(".vagrant-cache"),
+        };
+        let shlib = match &args.shlib {
+            Some(file) => absolute(file)?,
+            None => root.join("sh/lib.env"),
+        };
+
+        let config = Config::load(&root)?;
+        Ok(Self {
+            root,
+            cache,
+            shlib,
+            args,
+            config,
+        })
+    }
+
+    /// The aggregate formats to write
+    #[must_use]
+    pub fn formats(&self) -> &[Format] {
+        if self.args.formats.is_empty() {
+            &self.config.output.formats
+        } else {
+            &self.args.formats
+        }
+    }
+
+    /// How many packages to fetch at once
+    #[must_use]
+    pub fn jobs(&self) -> usize {
+        self.args.jobs.unwrap_or(DEFAULT_JOBS)
+    }
+
+    /// The directory containing all packages
+    #[must_use]
+    pub fn packages_dir(&self) -> PathBuf {
+        self.root.join("p")
+    }
+}
+
+fn absolute(path: &Path) -> Result<PathBuf> {
+    path::absolute(path).wrap_err_with(|| format!("Couldn't resolve {}", path.display()))
+}
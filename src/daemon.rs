@@ -0,0 +1,380 @@
+// daemon.rs
+//
+// A long-running alternative to one-shot runs. Each package is checked on its own schedule, every
+// `interval` if it has one, or else every `period / chance`, so a package with a chance of 0.25 is
+// checked about as often as it would be by one-shot runs every `period`. Due packages go through
+// the usual fetch pipeline, and the database is written after every batch. Status is served as
+// JSON to anything connecting to a Unix socket.
+
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use rand::random_range;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, error, info, warn};
+
+use crate::cache;
+use crate::context::Context;
+use crate::lock::Lock;
+use crate::notify;
+use crate::package::engine::{Engine, Log};
+use crate::package::fetcher::Fetcher;
+use crate::package::{Package, bulk};
+use crate::utils::duration;
+
+/// How often the daemon wakes to look for due packages
+const POLL: Duration = Duration::from_millis(250);
+
+/// How often packages are rediscovered, picking up added and removed packages
+const REFRESH: Duration = Duration::from_mins(1);
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// How often a package with a chance of 1 is checked
+    #[serde(deserialize_with = "duration::deserialize")]
+    pub period: Duration,
+    /// The status socket, relative to the root [default: <cache>/daemon.sock]
+    pub socket: Option<PathBuf>,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            period: Duration::from_hours(6),
+            socket: None,
+        }
+    }
+}
+
+struct Entry {
+    package: Package,
+    interval: Duration,
+    next: SystemTime,
+}
+
+/// What the daemon reports over its socket
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    pub started_at: String,
+    pub runs: u64,
+    /// Whether a batch is being checked right now
+    pub running: bool,
+    pub last_run: Option<RunStatus>,
+    /// Scheduled packages, soonest first
+    pub schedule: Vec<Scheduled>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunStatus {
+    pub at: String,
+    pub packages: Vec<String>,
+    pub changed: usize,
+    pub elapsed: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Scheduled {
+    pub package: String,
+    pub interval: String,
+    pub next_at: String,
+}
+
+pub struct Daemon<'a> {
+    ctx: Context,
+    fetcher: &'a dyn Fetcher,
+    schedule: Mutex<(Vec<Entry>, SystemTime)>,
+    status: Mutex<Status>,
+    stop: Arc<AtomicBool>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn rfc3339(t: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(t).to_string()
+}
+
+impl<'a> Daemon<'a> {
+    /// Schedule every package, due at once unless it was checked within its interval
+    ///
+    /// # Errors
+    ///
+    /// Fails if the packages can't be found.
+    pub fn new(mut ctx: Context, fetcher: &'a dyn Fetcher) -> Result<Self> {
+        // the schedule decides when packages are checked, so chance mustn't skip them again
+        ctx.args.guarantee = true;
+
+        let now = SystemTime::now();
+        let daemon = Self {
+            ctx,
+            fetcher,
+            schedule: Mutex::new((vec![], now)),
+            status: Mutex::new(Status {
+                started_at: rfc3339(now),
+                runs: 0,
+                running: false,
+                last_run: None,
+                schedule: vec![],
+            }),
+            stop: Arc::new(AtomicBool::new(false)),
+        };
+        daemon.refresh(now)?;
+        Ok(daemon)
+    }
+
+    #[must_use]
+    pub const fn ctx(&self) -> &Context {
+        &self.ctx
+    }
+
+    #[must_use]
+    pub fn socket(&self) -> PathBuf {
+        self.ctx.config.daemon.socket.as_ref().map_or_else(
+            || self.ctx.cache.join("daemon.sock"),
+            |s| self.ctx.root.join(s),
+        )
+    }
+
+    #[must_use]
+    pub fn status(&self) -> Status {
+        lock(&self.status).clone()
+    }
+
+    /// How often a package is checked, or none if it never is
+    fn interval(&self, package: &Package) -> Option<Duration> {
+        if let Some(interval) = package.config.interval {
+            return Some(interval);
+        }
+
+        let chance = package.config.chance.min(1.0);
+        (chance > 0.0)
+            .then(|| {
+                Duration::try_from_secs_f64(self.ctx.config.daemon.period.as_secs_f64() / chance)
+            })
+            .and_then(Result::ok)
+    }
+
+    /// Rediscover packages, keeping the schedule of known ones
+    ///
+    /// Packages are due an interval after their last successful check. New packages without
+    /// versions are due immediately, and others are spread over their interval, so a daemon
+    /// started on a database without check times doesn't check everything at once.
+    fn refresh(&self, now: SystemTime) -> Result<()> {
+        let mut schedule = lock(&self.schedule);
+        let (entries, refreshed) = &mut *schedule;
+
+        let mut fresh = vec![];
+        for package in bulk::find_all(&self.ctx)? {
+            let Some(interval) = self.interval(&package) else {
+                warn!("Never checking '{}' as its chance is 0", package.name);
+                continue;
+            };
+
+            let next = match entries.iter().find(|e| e.package.name == package.name) {
+                Some(e) if e.interval == interval => e.next,
+                _ => match package.last_checked(&self.ctx) {
+                    Some(t) => t + interval,
+                    None if !package.has_fallback_versions(&self.ctx) => now,
+                    None => now + interval.mul_f64(random_range(0.0..1.0)),
+                },
+            };
+            fresh.push(Entry {
+                package,
+                interval,
+                next,
+            });
+        }
+
+        fresh.sort_by_key(|e| e.next);
+        let scheduled = fresh
+            .iter()
+            .map(|e| Scheduled {
+                package: e.package.name.clone(),
+                interval: humantime::format_duration(e.interval).to_string(),
+                next_at: rfc3339(e.next),
+            })
+            .collect();
+        *entries = fresh;
+        *refreshed = now;
+        drop(schedule);
+
+        self.set_status(|s| s.schedule = scheduled);
+        Ok(())
+    }
+
+    fn set_status(&self, f: impl FnOnce(&mut Status)) {
+        f(&mut lock(&self.status));
+    }
+
+    /// Check and write every package due at `now`, returning how many were checked
+    ///
+    /// # Errors
+    ///
+    /// Fails if the packages can't be found, or the run can't be written.
+    pub fn tick(&self, now: SystemTime) -> Result<usize> {
+        let stale = {
+            let schedule = lock(&self.schedule);
+            now.duration_since(schedule.1).unwrap_or_default() >= REFRESH
+        };
+        if stale {
+            self.refresh(now)?;
+        }
+
+        let due = {
+            let schedule = lock(&self.schedule);
+            schedule
+                .0
+                .iter()
+                .filter(|e| e.next <= now)
+                .map(|e| e.package.clone())
+                .collect::<Vec<_>>()
+        };
+        if due.is_empty() {
+            return Ok(0);
+        }
+
+        let _lock = match Lock::acquire(&self.ctx) {
+            Ok(lock) => lock,
+            Err(e) => {
+                warn!("Postponing {} due packages: {e}", due.len());
+                return Ok(0);
+            }
+        };
+
+        self.set_status(|s| s.running = true);
+        let res = self.check(&due);
+        self.set_status(|s| s.running = false);
+        let (changed, elapsed) = res?;
+
+        for e in &mut lock(&self.schedule).0 {
+            if due.iter().any(|p| p.name == e.package.name) {
+                e.next = now + e.interval;
+            }
+        }
+        self.refresh(now)?;
+
+        self.set_status(|s| {
+            s.runs += 1;
+            s.last_run = Some(RunStatus {
+                at: rfc3339(now),
+                packages: due.iter().map(|p| p.name.clone()).collect(),
+                changed,
+                elapsed,
+            });
+        });
+        Ok(due.len())
+    }
+
+    /// Fetch `due` and write the database, returning the number of changes and the elapsed time
+    fn check(&self, due: &[Package]) -> Result<(usize, String)> {
+        let ctx = &self.ctx;
+        let start = Instant::now();
+        info!("Checking {} due packages", due.len());
+
+        let pruned = cache::prune(ctx)?;
+        debug!("Pruned {pruned} cache files");
+
+        // stopping the daemon lets fetches in flight finish, then writes what was fetched
+        let engine = Engine::new(ctx.jobs(), &Log, &self.stop);
+        let fetched = bulk::fetch_with(ctx, due, self.fetcher, &engine)?;
+
+        // packages that weren't due are written as they were, so the aggregates stay whole
+        let mut map = fetched;
+        for package in bulk::find_all(ctx)? {
+            if !map.contains_key(&package)
+                && let Some(versions) = package.read_previous_versions(ctx)?
+            {
+                map.insert(package, versions);
+            }
+        }
+        map.sort_keys();
+
+        let elapsed = humantime::format_duration(start.elapsed()).to_string();
+        if ctx.args.pretend {
+            return Ok((0, elapsed));
+        }
+
+        let changes = bulk::write_fetched(ctx, &map, due)?;
+        info!("{} channels changed", changes.len());
+        notify::notify_all(ctx, &changes);
+        bulk::increment_runcount(ctx)?;
+        fs::write(ctx.cache.join("elapsed"), &elapsed)?;
+
+        Ok((changes.len(), elapsed))
+    }
+
+    /// Check packages as they fall due until stopped
+    ///
+    /// # Errors
+    ///
+    /// Fails if the status socket can't be bound. Failed ticks are logged instead.
+    pub fn run(&self) -> Result<()> {
+        let path = self.socket();
+        if path.exists() {
+            fs::remove_file(&path)
+                .wrap_err_with(|| format!("Failed to remove stale socket {}", path.display()))?;
+        }
+        let listener = UnixListener::bind(&path)
+            .wrap_err_with(|| format!("Failed to bind {}", path.display()))?;
+        listener.set_nonblocking(true)?;
+        info!("Serving daemon status on {}", path.display());
+
+        thread::scope(|s| {
+            s.spawn(|| self.serve_status(&listener));
+
+            while !self.stop.load(Ordering::Relaxed) {
+                if let Err(e) = self.tick(SystemTime::now()) {
+                    error!("Failed to check due packages: {e:#}");
+                }
+                thread::sleep(POLL);
+            }
+        });
+
+        let _ = fs::remove_file(&path);
+        Ok(())
+    }
+
+    /// Stop running, returning from `run`
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// The flag that stops the daemon when set, for signal handlers
+    #[must_use]
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop)
+    }
+
+    fn serve_status(&self, listener: &UnixListener) {
+        while !self.stop.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((mut stream, _)) => {
+                    let res = serde_json::to_vec_pretty(&self.status())
+                        .map_err(std::io::Error::from)
+                        .and_then(|mut json| {
+                            json.push(b'\n');
+                            stream.set_nonblocking(false)?;
+                            stream.write_all(&json)
+                        });
+                    if let Err(e) = res {
+                        error!("Failed to send status: {e}");
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL / 5),
+                Err(e) => {
+                    error!("Failed to accept status connection: {e}");
+                    thread::sleep(POLL);
+                }
+            }
+        }
+    }
+}
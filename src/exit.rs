@@ -0,0 +1,111 @@
+// exit.rs
+//
+// Process exit codes, so CI can gate on a run without reading its report. A run where any package
+// failed or any channel regressed exits with `Failures`, or with `Threshold` if more packages
+// failed than `--max-failure-rate` allows or any package passed to `--fail-on` did.
+
+use color_eyre::Result;
+use std::fs;
+use std::process::ExitCode;
+use thiserror::Error;
+use tracing::{error, warn};
+
+use crate::context::Context;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Success,
+    /// Something went wrong that isn't covered below
+    Error,
+    /// The configuration or arguments are invalid, matching clap's usage errors
+    Config,
+    /// Some packages failed, or some channels regressed
+    Failures,
+    /// Too many packages failed, or one that mustn't
+    Threshold,
+}
+
+impl Exit {
+    #[must_use]
+    pub const fn code(self) -> u8 {
+        match self {
+            Self::Success => 0,
+            Self::Error => 1,
+            Self::Config => 2,
+            Self::Failures => 3,
+            Self::Threshold => 4,
+        }
+    }
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> Self {
+        Self::from(exit.code())
+    }
+}
+
+/// Marks an error as caused by invalid configuration, for [`Exit::Config`]
+#[derive(Error, Debug)]
+#[error("Invalid configuration")]
+pub struct ConfigError;
+
+/// A number from a run report in the cache
+fn report(ctx: &Context, name: &str) -> usize {
+    fs::read_to_string(ctx.cache.join(name))
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or_default()
+}
+
+/// How the last run should exit, from its report in the cache
+///
+/// The failure rate is the share of packages that failed among those that were checked or
+/// failed, so skipped packages don't dilute it.
+///
+/// # Errors
+///
+/// Fails if the report can't be read.
+pub fn judge(ctx: &Context) -> Result<Exit> {
+    let regressions = fs::read_to_string(ctx.cache.join("regressions"))
+        .unwrap_or_default()
+        .lines()
+        .count();
+    if regressions > 0 {
+        warn!("Refused {regressions} regressions");
+    }
+
+    let failed = report(ctx, "failed");
+    if failed == 0 {
+        return Ok(if regressions > 0 {
+            Exit::Failures
+        } else {
+            Exit::Success
+        });
+    }
+
+    let failed_packages = fs::read_to_string(ctx.cache.join("failed_packages"))?;
+    let mut exit = Exit::Failures;
+    for package in failed_packages.lines() {
+        if ctx.args.fail_on.iter().any(|p| p == package) {
+            error!("Package '{package}' failed");
+            exit = Exit::Threshold;
+        }
+    }
+
+    let attempted = failed + report(ctx, "checked");
+    #[allow(clippy::cast_precision_loss)]
+    let rate = failed as f64 / attempted as f64;
+    match ctx.args.max_failure_rate {
+        Some(max) if rate > max => {
+            error!(
+                "{failed} of {attempted} packages failed ({:.1}%), more than the {:.1}% allowed",
+                rate * 100.0,
+                max * 100.0
+            );
+            exit = Exit::Threshold;
+        }
+        _ => warn!("{failed} of {attempted} packages failed"),
+    }
+
+    Ok(exit)
+}
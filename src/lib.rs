@@ -0,0 +1,17 @@
+pub mod args;
+pub mod cache;
+pub mod commit;
+pub mod config;
+pub mod context;
+pub mod daemon;
+pub mod exit;
+pub mod lock;
+pub mod logging;
+pub mod notify;
+pub mod package;
+pub mod progress;
+pub mod record;
+pub mod schema;
+pub mod serve;
+pub mod site;
+pub mod utils;
@@ -0,0 +1,53 @@
+// lock.rs
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, bail};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Write;
+
+use crate::context::Context;
+
+/// An exclusive lock on a Vagrant root, held for as long as this lives
+///
+/// The lock is an advisory lock on `.vagrant.lock`, so it's released by the kernel even if
+/// Vagrant is killed.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    /// Take the lock on the root, held until dropped
+    ///
+    /// # Errors
+    ///
+    /// Fails if the lock file can't be opened or another run holds it.
+    pub fn acquire(ctx: &Context) -> Result<Self> {
+        let path = ctx.root.join(".vagrant.lock");
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                bail!(
+                    "Another vagrant run holds the lock on {}",
+                    ctx.root.display()
+                )
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(e).wrap_err_with(|| format!("Failed to lock {}", path.display()));
+            }
+        }
+
+        // record the holder, purely for the benefit of whoever finds the lock
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())?;
+
+        Ok(Self { _file: file })
+    }
+}
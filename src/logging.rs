@@ -0,0 +1,82 @@
+// logging.rs
+//
+// Log output. Logs go to stdout, beneath the progress line, and to `--log-file` if given, without
+// colors. Events carry the spans of the package and channel being fetched, each with its outcome
+// and duration in seconds once done, and JSON logs also record every span as it closes, so
+// consumers get a line per package and channel without scraping messages.
+
+use clap::ValueEnum;
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use std::env;
+use std::fs::File;
+use std::io::{self, IsTerminal};
+use std::sync::Mutex;
+use tracing::Subscriber;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::writer::MakeWriter;
+use tracing_subscriber::fmt::{self, time};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::args::Args;
+use crate::progress;
+
+#[derive(Debug, Default, Clone, Copy, ValueEnum)]
+pub enum LogFormat {
+    /// A JSON object per line
+    Json,
+    /// A line per event
+    #[default]
+    Compact,
+    /// Several lines per event, for reading by eye
+    Pretty,
+}
+
+/// A layer writing logs in `format` to `writer`
+pub fn layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = fmt::layer()
+        .with_level(true)
+        .with_target(true)
+        .with_line_number(true)
+        .with_timer(time::uptime())
+        .with_writer(writer)
+        .with_ansi(ansi);
+
+    match format {
+        LogFormat::Json => layer.json().with_span_events(FmtSpan::CLOSE).boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+        LogFormat::Pretty => layer.pretty().boxed(),
+    }
+}
+
+/// Log at `LOG_LEVEL` to stdout, and to `--log-file` if given
+///
+/// # Errors
+///
+/// Fails if the log file can't be opened or a subscriber is already set.
+pub fn init(args: &Args) -> Result<()> {
+    let level = env::var("LOG_LEVEL").unwrap_or_else(|_| String::from("info"));
+    let file = args
+        .log_file
+        .as_ref()
+        .map(|path| {
+            File::create(path)
+                .wrap_err_with(|| format!("Failed to create log file {}", path.display()))
+        })
+        .transpose()?;
+
+    let ansi = io::stdout().is_terminal();
+    tracing_subscriber::registry()
+        .with(EnvFilter::new(level))
+        .with(layer(args.log_format, || progress::Writer, ansi))
+        .with(file.map(|f| layer(args.log_format, Mutex::new(f), false)))
+        .init();
+    Ok(())
+}
@@ -1,115 +1,153 @@
+use clap::Parser;
 use color_eyre::config::HookBuilder;
-use color_eyre::eyre::WrapErr;
-use std::os::unix::fs::MetadataExt;
-use std::path::{Path, PathBuf};
-use std::sync::LazyLock;
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
-use std::{env, fs};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use std::fs;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::Instant;
 use tracing::{debug, info};
 
-use self::args::ARGS;
-use self::package::{Package, bulk};
 use color_eyre::Result;
-use tracing_subscriber::EnvFilter;
-use tracing_subscriber::fmt::time;
-
-mod args;
-mod package;
-mod utils;
+use color_eyre::eyre::WrapErr;
+use vagrant::args::{Args, CacheCommand, Command};
+use vagrant::cache;
+use vagrant::commit;
+use vagrant::context::Context;
+use vagrant::daemon::Daemon;
+use vagrant::exit::{self, ConfigError, Exit};
+use vagrant::lock::Lock;
+use vagrant::logging;
+use vagrant::notify;
+use vagrant::package::engine::Engine;
+use vagrant::package::fetcher::Dispatcher;
+use vagrant::package::{Package, bulk};
+use vagrant::progress;
+use vagrant::schema;
+use vagrant::serve;
+use vagrant::site;
+
+fn main() -> ExitCode {
+    let res = HookBuilder::default()
+        .display_env_section(true)
+        .display_location_section(true)
+        .add_default_filters()
+        .install()
+        .and_then(|()| run());
+
+    match res {
+        Ok(exit) => exit.into(),
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            if e.downcast_ref::<ConfigError>().is_some() {
+                Exit::Config.into()
+            } else {
+                Exit::Error.into()
+            }
+        }
+    }
+}
 
-/// Timeout for .vagrant-cache
-const CACHE_TIMEOUT: Duration = Duration::from_secs(3600); // 1 hr
+fn run() -> Result<Exit> {
+    let start_timestamp = Instant::now();
 
-static VAGRANT_ROOT: LazyLock<PathBuf> =
-    LazyLock::new(|| env::current_dir().expect("Couldn't get working directory"));
+    let args = Args::parse();
+    logging::init(&args)?;
 
-static VAGRANT_CACHE: LazyLock<PathBuf> = LazyLock::new(|| VAGRANT_ROOT.join(".vagrant-cache"));
+    let ctx = Context::new(args).wrap_err(ConfigError)?;
+    debug!("Determined Vagrant root to be {}", ctx.root.display());
 
-static SHLIB_PATH: LazyLock<PathBuf> = LazyLock::new(|| VAGRANT_ROOT.join("sh/lib.env"));
+    if matches!(ctx.args.command, Some(Command::Daemon)) {
+        let daemon = Daemon::new(ctx, &Dispatcher)?;
 
-static NO_CACHE: LazyLock<bool> = LazyLock::new(|| ARGS.no_cache);
+        // the first signal stops the daemon once the batch in flight is written, the second exits
+        // at once
+        let stop = daemon.stop_flag();
+        for signal in [SIGINT, SIGTERM] {
+            flag::register_conditional_shutdown(signal, 128 + signal, Arc::clone(&stop))?;
+            flag::register(signal, Arc::clone(&stop))?;
+        }
 
-fn main() -> color_eyre::Result<()> {
-    clean_cache()?;
-    let start_timestamp = Instant::now();
+        daemon.run()?;
+        return Ok(Exit::Success);
+    }
 
-    HookBuilder::default()
-        .display_env_section(true)
-        .display_location_section(true)
-        .add_default_filters()
-        .install()?;
+    if let Some(command) = &ctx.args.command {
+        run_command(&ctx, command)?;
+        return Ok(Exit::Success);
+    }
 
-    log();
+    let _lock = Lock::acquire(&ctx)?;
 
-    debug!("Determined Vagrant root to be {}", VAGRANT_ROOT.display());
+    let pruned = cache::prune(&ctx)?;
+    debug!("Pruned {pruned} cache files");
 
-    let packages = if ARGS.packages.is_empty() {
-        bulk::find_all()?
+    let packages = if ctx.args.packages.is_empty() {
+        bulk::find_all(&ctx)
     } else {
-        ARGS.packages
+        ctx.args
+            .packages
             .iter()
-            .map(|s| Package::from_name(s.clone()))
-            .collect::<Result<Vec<_>>>()?
-    };
+            .map(|s| Package::from_name(&ctx, s.clone()))
+            .collect::<Result<Vec<_>>>()
+    }
+    .wrap_err(ConfigError)?;
 
     debug!("Detected packages: {packages:#?}");
-    let map = bulk::fetch_all(&packages)?;
+
+    // the first interrupt stops new fetches and lets the run finish, the second exits at once
+    let cancel = Arc::new(AtomicBool::new(false));
+    flag::register_conditional_shutdown(SIGINT, 130, Arc::clone(&cancel))?;
+    flag::register(SIGINT, Arc::clone(&cancel))?;
+
+    let map = {
+        let observer = progress::observer();
+        let engine = Engine::new(ctx.jobs(), observer.as_ref(), &cancel);
+        bulk::fetch_with(&ctx, &packages, &Dispatcher, &engine)?
+    };
 
     let elapsed = humantime::format_duration(start_timestamp.elapsed()).to_string();
 
-    if !ARGS.pretend {
-        bulk::write_all(&map)?;
-        increment_runcount()?;
+    if !ctx.args.pretend {
+        let changes = bulk::write_all(&ctx, &map)?;
+        info!("{} channels changed", changes.len());
+        notify::notify_all(&ctx, &changes);
+        bulk::increment_runcount(&ctx)?;
         debug!("Incremented runcount");
-        fs::write(VAGRANT_CACHE.join("elapsed"), &elapsed)?;
+        fs::write(ctx.cache.join("elapsed"), &elapsed)?;
     }
 
     info!("Finished in {elapsed}");
-    Ok(())
-}
-
-fn log() {
-    let level = env::var("LOG_LEVEL").unwrap_or_else(|_| String::from("info"));
-    let filter = EnvFilter::new(level);
-
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_level(true)
-        .with_target(true)
-        .with_line_number(true)
-        .with_timer(time::uptime())
-        .with_writer(std::io::stdout)
-        .compact()
-        .init();
-}
-
-fn increment_runcount() -> Result<()> {
-    let path = Path::new("runcount");
-    let runcount = fs::read_to_string(path)
-        .ok()
-        .and_then(|s| s.trim().parse::<u64>().ok())
-        .unwrap_or(0u64)
-        + 1;
-    fs::write(path, runcount.to_string())?;
-    Ok(())
+    exit::judge(&ctx)
 }
 
-fn clean_cache() -> Result<()> {
-    let cache_path = &*VAGRANT_CACHE;
-    if let Ok(m) = cache_path.metadata() {
-        #[allow(clippy::cast_sign_loss)]
-        let mtime = Duration::from_secs(m.mtime() as u64);
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .wrap_err("Time travel detected")?;
-
-        if now - mtime > CACHE_TIMEOUT {
-            debug!("Removing cache");
-            fs::remove_dir_all(cache_path).wrap_err("Failed to remove cache")?;
-            fs::create_dir(cache_path).wrap_err("Failed to create cache")?;
+fn run_command(ctx: &Context, command: &Command) -> Result<()> {
+    match command {
+        Command::Cache(CacheCommand::Ls) => cache::ls(ctx)?,
+        Command::Cache(CacheCommand::Clear) => {
+            let _lock = Lock::acquire(ctx)?;
+            let removed = cache::clear(ctx)?;
+            info!("Removed {removed} cache files");
         }
-    } else {
-        fs::create_dir(cache_path).wrap_err("Failed to create cache")?;
+        Command::Cache(CacheCommand::Prune) => {
+            let _lock = Lock::acquire(ctx)?;
+            let removed = cache::prune(ctx)?;
+            info!("Pruned {removed} cache files");
+        }
+        Command::Commit { no_push } => {
+            let _lock = Lock::acquire(ctx)?;
+            let commits = commit::commit(ctx, !no_push)?;
+            info!("Made {commits} commits");
+        }
+        Command::Site { out, base_url } => {
+            let out = out.clone().unwrap_or_else(|| ctx.root.join("site"));
+            let pages = site::build(ctx, &out, base_url)?;
+            info!("Rendered {pages} pages to {}", out.display());
+        }
+        Command::Serve { listen } => serve::Server::bind(ctx, listen)?.run(),
+        Command::Schema { kind } => print!("{}", schema::generate(*kind)),
+        Command::Daemon => unreachable!("the daemon takes ownership of the context"),
     }
 
     Ok(())
@@ -0,0 +1,187 @@
+// notify.rs
+//
+// Notifiers for version changes. After the database is written, each notifier configured under
+// `[[notify]]` in `vagrant.toml` is sent the changes to packages matching its tags, as a JSON
+// payload. Notifiers are best-effort: a failure is logged, but never fails the run.
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{debug, error, info};
+
+use crate::context::Context;
+use crate::package::VersionChange;
+use crate::utils::cmd;
+
+/// How long a webhook or socket may take before it's given up on
+const TIMEOUT: Duration = Duration::from_secs(16);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NotifierConfig {
+    /// Only notify of changes to packages with any of these tags, or all packages if empty
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(flatten)]
+    pub sink: Sink,
+}
+
+/// Where a notifier sends its payload
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sink {
+    /// A url to POST to
+    Webhook(String),
+    /// A Unix socket to connect to
+    Socket(PathBuf),
+    /// A named pipe to write to, if something is reading it
+    Pipe(PathBuf),
+    /// A bash command, run from the Vagrant root with the payload on stdin
+    Hook(String),
+}
+
+impl Display for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Webhook(url) => write!(f, "webhook {url}"),
+            Self::Socket(path) => write!(f, "socket {}", path.display()),
+            Self::Pipe(path) => write!(f, "pipe {}", path.display()),
+            Self::Hook(cmd) => write!(f, "hook '{cmd}'"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    /// The version of Vagrant that sent the payload
+    vagrant: &'static str,
+    changes: Vec<&'a VersionChange>,
+}
+
+impl NotifierConfig {
+    fn matches(&self, change: &VersionChange) -> bool {
+        self.tags.is_empty() || self.tags.iter().any(|t| change.tags.contains(t))
+    }
+
+    /// Send the matching changes, if there are any
+    ///
+    /// Returns whether anything was sent.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the notifier command fails.
+    pub fn notify(&self, ctx: &Context, changes: &[VersionChange]) -> Result<bool> {
+        let changes = changes
+            .iter()
+            .filter(|c| self.matches(c))
+            .collect::<Vec<_>>();
+        if changes.is_empty() {
+            debug!("No changes for {}", self.sink);
+            return Ok(false);
+        }
+
+        let mut payload = serde_json::to_vec(&Payload {
+            vagrant: env!("CARGO_PKG_VERSION"),
+            changes,
+        })?;
+        payload.push(b'\n');
+
+        self.sink.send(ctx, &payload)?;
+        Ok(true)
+    }
+}
+
+impl Sink {
+    fn send(&self, ctx: &Context, payload: &[u8]) -> Result<()> {
+        match self {
+            Self::Webhook(url) => {
+                let max_time = TIMEOUT.as_secs().to_string();
+                let out = cmd::pipe(
+                    &[
+                        "curl",
+                        "-fsS",
+                        "--max-time",
+                        &max_time,
+                        "-H",
+                        "Content-Type: application/json",
+                        "--data-binary",
+                        "@-",
+                        url,
+                    ],
+                    HashMap::new(),
+                    &ctx.root.to_string_lossy(),
+                    payload,
+                )?;
+                if out.code != 0 {
+                    bail!(
+                        "curl exited with status {}: {}",
+                        out.code,
+                        out.stderr.trim()
+                    );
+                }
+            }
+            Self::Socket(path) => {
+                let mut stream = UnixStream::connect(ctx.root.join(path))
+                    .wrap_err("Failed to connect to socket")?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                stream.write_all(payload)?;
+            }
+            Self::Pipe(path) => {
+                let path = ctx.root.join(path);
+
+                // opening a pipe for writing blocks until it has a reader, so probe for one first
+                let _probe = OpenOptions::new()
+                    .write(true)
+                    .custom_flags(libc::O_NONBLOCK)
+                    .open(&path)
+                    .wrap_err("Nothing is reading the pipe")?;
+
+                OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .wrap_err("Failed to open pipe")?
+                    .write_all(payload)?;
+            }
+            Self::Hook(hook) => {
+                let out = cmd::pipe(
+                    &["bash", "-c", hook],
+                    HashMap::new(),
+                    &ctx.root.to_string_lossy(),
+                    payload,
+                )?;
+                if out.code != 0 {
+                    bail!(
+                        "Hook exited with status {}: {}",
+                        out.code,
+                        out.stderr.trim()
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Run every configured notifier, returning the number that failed
+pub fn notify_all(ctx: &Context, changes: &[VersionChange]) -> usize {
+    let mut failed = 0;
+    for notifier in &ctx.config.notify {
+        match notifier.notify(ctx, changes) {
+            Ok(true) => info!("Notified {}", notifier.sink),
+            Ok(false) => {}
+            Err(e) => {
+                error!("Failed to notify {}: {e:#}", notifier.sink);
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
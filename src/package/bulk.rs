@@ -1,19 +1,32 @@
 // package/bulk.rs
 
-use crate::VAGRANT_CACHE;
-use crate::package::PackageVersions;
+use crate::context::Context;
+use crate::package::{PackageVersions, SCHEMA_VERSION};
+use crate::schema::{self, SchemaKind};
+use crate::utils::fs::Transaction;
+use crate::utils::ver;
 
-use super::{Package, VersionChannel};
+use super::engine::{Engine, Log, Outcome};
+use super::fetcher::Fetcher;
+use super::formats::Format;
+use super::throttle::Hosts;
+use super::{Package, Status, VersionChange, VersionChannel};
 use color_eyre::Result;
-use color_eyre::eyre::{Context, ContextCompat, Error};
+use color_eyre::eyre::{ContextCompat, WrapErr};
 use indexmap::IndexMap;
-use rayon::prelude::*;
-use std::path::Path;
-use std::{env, fs};
-use tracing::{debug, error};
+use std::cmp::{Ordering, Reverse};
+use std::fs;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, error, info, warn};
 
-pub fn find_all() -> Result<Vec<Package>> {
-    let search_path = Path::new("p");
+/// Find every package configured under `p/`, sorted by name
+///
+/// # Errors
+///
+/// Fails if `p/` can't be read or a package config is invalid.
+pub fn find_all(ctx: &Context) -> Result<Vec<Package>> {
+    let search_path = ctx.packages_dir();
     let mut packages = Vec::with_capacity(512);
 
     for entry in search_path.read_dir()?.flatten() {
@@ -27,7 +40,7 @@ pub fn find_all() -> Result<Vec<Package>> {
                 .to_string();
 
             packages.push(
-                Package::from_name(file_name.clone())
+                Package::from_name(ctx, file_name.clone())
                     .wrap_err_with(|| format!("Failed to form package '{file_name}'"))?,
             );
         }
@@ -37,71 +50,139 @@ pub fn find_all() -> Result<Vec<Package>> {
     Ok(packages)
 }
 
-pub fn fetch_all(packages: &[Package]) -> Result<IndexMap<Package, Vec<VersionChannel>>> {
-    let threads = env::var("RAYON_NUM_THREADS")
-        .ok()
-        .and_then(|v| v.parse::<usize>().ok())
-        .unwrap_or_else(|| num_cpus::get() * 2);
-
-    let pool = rayon::ThreadPoolBuilder::new()
-        .num_threads(threads)
-        .build()
-        .expect("Failed to create thread pool");
-
-    let res = pool
-        .install(|| {
-            packages
-                .par_iter()
-                .map(|package| {
-                    let mut skipped = 0;
-                    let mut failed = 0;
-
-                    let versions = match package.fetch() {
-                        Ok(v) => v,
-                        Err(e) if e.to_string().contains("Tails!") => {
-                            skipped = 1;
-                            debug!("Skipped fetching versions for package '{}'", package.name);
-                            package.read_versions().wrap_err_with(|| {
-                                format!(
-                                    "Failed to read old versions for skipped package '{}'",
-                                    package.name
-                                )
-                            })?
-                        }
-                        Err(e) => {
-                            failed = 1;
-                            error!("Failed to fetch versions for {}: {e}", package.name);
-                            package.read_versions().wrap_err_with(|| {
-                                format!(
-                                    "Failed to read old versions for failed package '{}'",
-                                    package.name
-                                )
-                            })?
-                        }
-                    };
-
-                    Ok::<_, Error>((package.clone(), versions, skipped, failed))
-                })
-                .collect::<Result<Vec<_>, _>>()
+/// Fetch every package on an engine with `--jobs` workers, logging progress
+///
+/// # Errors
+///
+/// Fails if the run report can't be written.
+pub fn fetch_all(
+    ctx: &Context,
+    packages: &[Package],
+    fetcher: &dyn Fetcher,
+) -> Result<IndexMap<Package, Vec<VersionChannel>>> {
+    let cancel = AtomicBool::new(false);
+    let engine = Engine::new(ctx.jobs(), &Log, &cancel);
+    fetch_with(ctx, packages, fetcher, &engine)
+}
+
+/// Fetch every package on `engine`, writing the run report to the cache
+///
+/// Packages that fail or are skipped fall back to their previous versions, as do those never
+/// started because the run was cancelled. Each package's fetch time is written to `timings`,
+/// slowest first.
+///
+/// # Errors
+///
+/// Fails if the run report can't be written.
+pub fn fetch_with(
+    ctx: &Context,
+    packages: &[Package],
+    fetcher: &dyn Fetcher,
+    engine: &Engine,
+) -> Result<IndexMap<Package, Vec<VersionChannel>>> {
+    let start = Instant::now();
+    let hosts = Hosts::new(&ctx.config.hosts);
+
+    // decided up front, so skipped packages neither take up workers nor count against their hosts
+    let skipped = packages
+        .iter()
+        .map(|p| p.is_skipped(ctx))
+        .collect::<Vec<_>>();
+    let due = packages
+        .iter()
+        .zip(&skipped)
+        .filter(|(_, skip)| !**skip)
+        .map(|(p, _)| p.clone())
+        .collect::<Vec<_>>();
+    debug!(
+        "Fetching {} of {} packages with {} jobs",
+        due.len(),
+        packages.len(),
+        engine.jobs()
+    );
+
+    let mut results = engine
+        .run_limited(&due, &hosts, |package| {
+            let res = fetch_one(ctx, package, fetcher);
+            let outcome = match &res {
+                Ok(f) if !f.failed => Outcome::Checked,
+                _ => Outcome::Failed,
+            };
+            (res, outcome)
         })
-        .wrap_err("Failed to bulk fetch versions")?;
+        .into_iter();
 
     let mut map = IndexMap::new();
     let mut skipped_count = 0;
     let mut failed_count = 0;
+    let mut cancelled = 0;
+    let mut failed_packages = String::new();
+    let mut failed_channels = String::new();
+    let mut timings = vec![];
+
+    for (package, skip) in packages.iter().zip(skipped) {
+        let res = if skip {
+            skip_one(ctx, package)?
+        } else if let Some(done) = results.next().flatten() {
+            timings.push((&package.name, done.elapsed));
+            done.value.wrap_err("Failed to bulk fetch versions")?
+        } else {
+            cancelled += 1;
+            PackageResult {
+                versions: package
+                    .read_previous_versions(ctx)?
+                    .map(|v| with_status(v, Status::Skipped)),
+                skipped: true,
+                failed: false,
+                failed_channels: vec![],
+            }
+        };
+
+        for channel in &res.failed_channels {
+            failed_channels = format!("{failed_channels}{}:{channel}\n", package.name);
+        }
+        skipped_count += usize::from(res.skipped);
+        if res.failed {
+            failed_count += 1;
+            failed_packages = format!("{failed_packages}{}\n", package.name);
+        }
+        if let Some(versions) = res.versions {
+            map.insert(package.clone(), versions);
+        }
+    }
+
+    if cancelled > 0 {
+        warn!("Cancelled before checking {cancelled} packages, keeping their previous versions");
+    }
 
-    for (pkg, ver, skipped, failed) in res {
-        map.insert(pkg, ver);
-        skipped_count += skipped;
-        failed_count += failed;
+    timings.sort_by_key(|(_, elapsed)| Reverse(*elapsed));
+    let timings = timings.iter().fold(String::new(), |acc, (name, elapsed)| {
+        format!("{acc}{name}\t{:.3}\n", elapsed.as_secs_f64())
+    });
+
+    let elapsed = humantime::format_duration(Duration::from_millis(
+        u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX),
+    ));
+    if let Ok(previous) = fs::read_to_string(ctx.cache.join("elapsed")) {
+        info!(
+            "Fetched in {elapsed}, the previous run took {}",
+            previous.trim()
+        );
+    } else {
+        info!("Fetched in {elapsed}");
     }
 
-    let total = map.len();
-    fs::write(VAGRANT_CACHE.join("total"), total.to_string())?;
-    fs::write(VAGRANT_CACHE.join("failed"), failed_count.to_string())?;
-    fs::write(VAGRANT_CACHE.join("skipped"), skipped_count.to_string())?;
+    let total = packages.len();
+    fs::write(ctx.cache.join("total"), total.to_string())?;
+    fs::write(ctx.cache.join("failed"), failed_count.to_string())?;
+    fs::write(ctx.cache.join("skipped"), skipped_count.to_string())?;
+    fs::write(ctx.cache.join("failed_packages"), failed_packages)?;
+    fs::write(ctx.cache.join("failed_channels"), failed_channels)?;
+    // filled in by write_all, if it's reached
+    fs::write(ctx.cache.join("regressions"), "")?;
+    fs::write(ctx.cache.join("timings"), timings)?;
     fs::write(
-        VAGRANT_CACHE.join("checked"),
+        ctx.cache.join("checked"),
         (total - failed_count - skipped_count).to_string(),
     )?;
     map.sort_keys();
@@ -109,29 +190,243 @@ pub fn fetch_all(packages: &[Package]) -> Result<IndexMap<Package, Vec<VersionCh
     Ok(map)
 }
 
-pub fn write_all(map: &IndexMap<Package, Vec<VersionChannel>>) -> Result<()> {
+/// What came of fetching a package
+struct PackageResult {
+    /// The versions to write, or none for a new package without any
+    versions: Option<Vec<VersionChannel>>,
+    skipped: bool,
+    /// Whether the package or any of its channels failed
+    failed: bool,
+    failed_channels: Vec<String>,
+}
+
+/// Keep the previous versions of a package skipped this run
+fn skip_one(ctx: &Context, package: &Package) -> Result<PackageResult> {
+    debug!("Skipped fetching versions for package '{}'", package.name);
+    let versions = package
+        .read_previous_versions(ctx)
+        .wrap_err_with(|| {
+            format!(
+                "Failed to read old versions for skipped package '{}'",
+                package.name
+            )
+        })?
+        .map(|v| with_status(v, Status::Skipped));
+
+    Ok(PackageResult {
+        versions,
+        skipped: true,
+        failed: false,
+        failed_channels: vec![],
+    })
+}
+
+fn fetch_one(ctx: &Context, package: &Package, fetcher: &dyn Fetcher) -> Result<PackageResult> {
+    let mut failed = true;
+    let mut failed_channels = vec![];
+
+    // new packages have no versions to fall back on, so they're left out
+    let versions = match package.fetch(ctx, fetcher) {
+        Ok(outcome) => {
+            failed = !outcome.failed.is_empty();
+            failed_channels = outcome.failed;
+            if outcome.versions.is_empty() && failed {
+                package.read_previous_versions(ctx)?
+            } else {
+                Some(outcome.versions)
+            }
+        }
+        Err(e) => {
+            error!("Failed to fetch versions for {}: {e}", package.name);
+            package
+                .read_previous_versions(ctx)
+                .wrap_err_with(|| {
+                    format!(
+                        "Failed to read old versions for failed package '{}'",
+                        package.name
+                    )
+                })?
+                .map(|v| with_status(v, Status::Failed))
+        }
+    };
+
+    if versions.is_none() {
+        error!(
+            "No versions for new package '{}', it won't be written",
+            package.name
+        );
+    }
+
+    Ok(PackageResult {
+        versions,
+        skipped: false,
+        failed,
+        failed_channels,
+    })
+}
+
+/// Mark versions read back from a previous run with how they were arrived at in this one
+fn with_status(mut versions: Vec<VersionChannel>, status: Status) -> Vec<VersionChannel> {
+    for vc in &mut versions {
+        vc.status = Some(status);
+    }
+    versions
+}
+
+/// Write version data for all packages, then the selected aggregate formats
+///
+/// Every file is staged before any is replaced, so a failure leaves the database untouched.
+/// Channels that were removed or disabled are dropped and their files pruned, unless
+/// `--keep-stale` is passed. Channels are marked frozen as currently configured, since versions
+/// may have been read back from a run with a different config. Metadata is dropped if the
+/// configured schema version predates it. Monotonic channels whose versions went backwards keep
+/// their previous values, and are listed in `regressions`. Returns the channels whose versions
+/// changed.
+///
+/// # Errors
+///
+/// Fails if previous versions can't be read or the files can't be written.
+pub fn write_all(
+    ctx: &Context,
+    map: &IndexMap<Package, Vec<VersionChannel>>,
+) -> Result<Vec<VersionChange>> {
+    write(ctx, map, |_| true)
+}
+
+/// Write version data as [`write_all`] does, where only packages in `fetched` were fetched this
+/// run and the rest are carried forward as they were
+///
+/// Carried packages keep their statuses, and their `last_checked` isn't touched.
+///
+/// # Errors
+///
+/// Fails as [`write_all`] does.
+pub fn write_fetched(
+    ctx: &Context,
+    map: &IndexMap<Package, Vec<VersionChannel>>,
+    fetched: &[Package],
+) -> Result<Vec<VersionChange>> {
+    write(ctx, map, |package| fetched.contains(package))
+}
+
+fn write(
+    ctx: &Context,
+    map: &IndexMap<Package, Vec<VersionChannel>>,
+    fetched: impl Fn(&Package) -> bool,
+) -> Result<Vec<VersionChange>> {
+    let mut txn = Transaction::new();
     let mut all_vec = vec![];
+    let mut pruned = vec![];
+    let mut regressions = vec![];
+    let mut changes = vec![];
+    let now = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
 
     for (k, v) in map {
-        k.write_versions(v.clone())?;
-        all_vec.push(PackageVersions {
-            package: k.name.clone(),
-            versions: v.clone(),
-        });
+        let mut v = if ctx.args.keep_stale {
+            v.clone()
+        } else {
+            for channel in k.stage_prune(ctx, &mut txn)? {
+                info!("Pruning stale channel {}:{channel}", k.name);
+                pruned.push(format!("{}:{channel}", k.name));
+            }
+
+            v.iter()
+                .filter(|c| k.is_live(&c.channel))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        let old = k.read_previous_versions(ctx)?.unwrap_or_default();
+        for c in &mut v {
+            if let Some(regression) = refuse_regression(k, &old, c) {
+                error!("Refusing regression of {regression}");
+                regressions.push(regression);
+            }
+        }
+
+        // a package counts as checked only if none of its channels fell back
+        if fetched(k)
+            && v.iter().any(|c| c.status == Some(Status::Fresh))
+            && !v.iter().any(|c| c.status == Some(Status::Fallback))
+        {
+            txn.stage(k.get_package_path(ctx).join("last_checked"), &now)?;
+        }
+
+        for c in &mut v {
+            c.frozen = k.is_frozen(&c.channel);
+            if ctx.config.output.schema < 2 {
+                c.strip_metadata();
+            }
+        }
+
+        changes.extend(VersionChange::between(k, &old, &v));
+
+        k.stage_versions(ctx, &mut txn, &v)?;
+        all_vec.push(PackageVersions::new(&k.name, v));
     }
 
-    let path = Path::new("p");
+    let path = ctx.packages_dir();
 
-    let alljson = serde_json::to_string_pretty(&all_vec)?;
-    fs::write(path.join("ALL.json"), alljson)?;
+    // staged last so they're only replaced once every package has been written
+    for format in ctx.formats() {
+        txn.stage(path.join(format.file_name()), format.render(ctx, &all_vec)?)?;
+    }
 
-    let mut alltxt = String::new();
-    for p in all_vec {
-        for c in p.versions {
-            alltxt = format!("{alltxt}{}\t{}\t{}\n", p.package, c.channel, c.version);
+    // older schema versions are written for compatibility, but only the current one is described
+    if ctx.formats().contains(&Format::Json) && ctx.config.output.schema == SCHEMA_VERSION {
+        for kind in SchemaKind::ALL {
+            txn.stage(path.join(kind.file_name()), schema::generate(kind))?;
         }
     }
-    fs::write(path.join("ALL.txt"), alltxt)?;
 
+    txn.commit()?;
+
+    let pruned = pruned.iter().fold(String::new(), |acc, p| acc + p + "\n");
+    fs::write(ctx.cache.join("pruned"), pruned)?;
+    let regressions = regressions
+        .iter()
+        .fold(String::new(), |acc, r| acc + r + "\n");
+    fs::write(ctx.cache.join("regressions"), regressions)?;
+    Ok(changes)
+}
+
+/// Put back the previous value of a monotonic channel whose version went backwards, returning the
+/// regression as `package:channel | new < old`
+fn refuse_regression(
+    package: &Package,
+    old: &[VersionChannel],
+    channel: &mut VersionChannel,
+) -> Option<String> {
+    let previous = old.iter().find(|o| o.channel == channel.channel)?;
+    if !package.is_monotonic(&channel.channel)
+        || ver::compare(&channel.version, &previous.version) != Ordering::Less
+    {
+        return None;
+    }
+
+    let regression = format!(
+        "{}:{} | {} < {}",
+        package.name, channel.channel, channel.version, previous.version
+    );
+    *channel = VersionChannel {
+        status: Some(Status::Fallback),
+        ..previous.clone()
+    };
+    Some(regression)
+}
+
+/// Count a completed run, for commit messages
+///
+/// # Errors
+///
+/// Fails if the run count can't be read or written.
+pub fn increment_runcount(ctx: &Context) -> Result<()> {
+    let path = ctx.root.join("runcount");
+    let runcount = fs::read_to_string(&path)
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or(0u64)
+        + 1;
+    fs::write(path, runcount.to_string())?;
     Ok(())
 }
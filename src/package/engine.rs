@@ -0,0 +1,332 @@
+// package/engine.rs
+//
+// Runs package fetches on a bounded pool of worker threads. Fetching is spent almost entirely
+// waiting on processes and the network, so the pool is sized for fetches in flight rather than for
+// cores. Workers take packages off a shared queue as they free up, so none are taken on before a
+// worker is free to start them, and a cancelled run stops taking on packages while those in flight
+// finish. Packages whose hosts are at their limits are deferred while workers take on later ones.
+
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, field, info, info_span};
+
+use super::Package;
+use super::throttle::{Admission, Hosts, Wait};
+
+/// Fetches in flight when `--jobs` isn't given
+pub const DEFAULT_JOBS: usize = 64;
+
+/// How a package's fetch turned out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Checked,
+    Skipped,
+    Failed,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Checked => "checked",
+            Self::Skipped => "skipped",
+            Self::Failed => "failed",
+        };
+        write!(f, "{s}")
+    }
+}
+
+#[derive(Debug)]
+pub enum Event<'a> {
+    Started(&'a Package),
+    Finished {
+        package: &'a Package,
+        outcome: Outcome,
+        elapsed: Duration,
+    },
+}
+
+/// How far through a run the engine is, as of an event
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub total: usize,
+    pub completed: usize,
+    pub in_flight: usize,
+    pub failed: usize,
+    pub started_at: Instant,
+}
+
+impl Progress {
+    /// How long the rest of the run should take at the pace so far, to the second
+    #[must_use]
+    pub fn eta(&self) -> Option<Duration> {
+        let completed = u32::try_from(self.completed).ok().filter(|&c| c > 0)?;
+        let left = u32::try_from(self.total - self.completed).ok()?;
+        let eta = self.started_at.elapsed() * left / completed;
+        Some(Duration::from_secs(
+            eta.as_secs() + u64::from(eta.subsec_millis() >= 500),
+        ))
+    }
+}
+
+pub trait Observer: Sync {
+    /// Called on the thread running the engine, in the order events happened
+    fn event(&self, event: &Event<'_>, progress: &Progress);
+}
+
+/// Ignores every event
+pub struct Quiet;
+
+impl Observer for Quiet {
+    fn event(&self, _event: &Event<'_>, _progress: &Progress) {}
+}
+
+/// Logs each package as it finishes, and overall progress every tenth of the way
+pub struct Log;
+
+impl Observer for Log {
+    fn event(&self, event: &Event<'_>, progress: &Progress) {
+        let Event::Finished {
+            package,
+            outcome,
+            elapsed,
+        } = event
+        else {
+            return;
+        };
+
+        debug!("Package '{}' {outcome} in {elapsed:.2?}", package.name);
+        let step = progress.total.div_ceil(10).max(1);
+        if progress.completed.is_multiple_of(step) || progress.completed == progress.total {
+            let eta = progress.eta().unwrap_or_default();
+            info!(
+                "Finished {}/{} packages ({} in flight, {} failed, {} left)",
+                progress.completed,
+                progress.total,
+                progress.in_flight,
+                progress.failed,
+                humantime::format_duration(eta)
+            );
+        }
+    }
+}
+
+pub struct Engine<'a> {
+    jobs: usize,
+    observer: &'a dyn Observer,
+    cancel: &'a AtomicBool,
+}
+
+/// A package's result, and how long it took
+pub struct Done<T> {
+    pub value: T,
+    pub elapsed: Duration,
+}
+
+enum Message<T> {
+    Started(usize),
+    Finished(usize, T, Outcome, Duration),
+}
+
+/// Packages not yet started, in the order they're to be taken on
+struct Queue {
+    /// The next package never looked at
+    next: usize,
+    /// Packages looked at but not admitted by their hosts, oldest first
+    deferred: Vec<usize>,
+}
+
+impl Queue {
+    /// Take the first package its hosts admit, deferring those they don't, or say how long to
+    /// wait for one
+    fn take<'h>(
+        &mut self,
+        packages: &[Package],
+        hosts: &'h Hosts,
+    ) -> Result<(usize, Admission<'h>), Option<Wait>> {
+        let mut wait = None::<Wait>;
+        let mut earliest = |w: Wait| {
+            wait = Some(match (wait, w) {
+                (Some(Wait::For(a)), Wait::For(b)) => Wait::For(a.min(b)),
+                (Some(Wait::For(a)), Wait::Release) | (Some(Wait::Release), Wait::For(a)) => {
+                    Wait::For(a)
+                }
+                _ => w,
+            });
+        };
+
+        for (d, &i) in self.deferred.iter().enumerate() {
+            match hosts.try_admit(&packages[i]) {
+                Ok(admission) => {
+                    self.deferred.remove(d);
+                    return Ok((i, admission));
+                }
+                Err(w) => earliest(w),
+            }
+        }
+
+        while self.next < packages.len() {
+            let i = self.next;
+            self.next += 1;
+            match hosts.try_admit(&packages[i]) {
+                Ok(admission) => return Ok((i, admission)),
+                Err(w) => {
+                    earliest(w);
+                    self.deferred.push(i);
+                }
+            }
+        }
+
+        Err(wait)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<'a> Engine<'a> {
+    pub fn new(jobs: usize, observer: &'a dyn Observer, cancel: &'a AtomicBool) -> Self {
+        Self {
+            jobs: jobs.max(1),
+            observer,
+            cancel,
+        }
+    }
+
+    #[must_use]
+    pub const fn jobs(&self) -> usize {
+        self.jobs
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// Run `f` on every package, returning results in the order of `packages`
+    ///
+    /// Packages not started before the run was cancelled have no result.
+    pub fn run<T, F>(&self, packages: &[Package], f: F) -> Vec<Option<Done<T>>>
+    where
+        T: Send,
+        F: Fn(&Package) -> (T, Outcome) + Sync,
+    {
+        self.run_limited(packages, &Hosts::default(), f)
+    }
+
+    /// Run `f` on every package as [`run`](Self::run) does, starting each only once its hosts
+    /// admit it
+    ///
+    /// Workers take on later packages while earlier ones wait on their hosts, and only wait
+    /// themselves when every package left is waiting.
+    pub fn run_limited<T, F>(
+        &self,
+        packages: &[Package],
+        hosts: &Hosts,
+        f: F,
+    ) -> Vec<Option<Done<T>>>
+    where
+        T: Send,
+        F: Fn(&Package) -> (T, Outcome) + Sync,
+    {
+        let mut results = packages.iter().map(|_| None).collect::<Vec<_>>();
+        let mut progress = Progress {
+            total: packages.len(),
+            completed: 0,
+            in_flight: 0,
+            failed: 0,
+            started_at: Instant::now(),
+        };
+
+        let queue = Mutex::new(Queue {
+            next: 0,
+            deferred: vec![],
+        });
+        let released = Condvar::new();
+        let (done_tx, done_rx) = mpsc::channel::<Message<T>>();
+
+        thread::scope(|s| {
+            for _ in 0..self.jobs.min(packages.len()) {
+                let (queue, released, done_tx, f) = (&queue, &released, done_tx.clone(), &f);
+                s.spawn(move || {
+                    loop {
+                        let mut q = lock(queue);
+                        let (i, admission) = loop {
+                            if self.is_cancelled() {
+                                return;
+                            }
+                            q = match q.take(packages, hosts) {
+                                Ok(taken) => break taken,
+                                // nothing is left, or only packages waiting on those in flight
+                                Err(None) => return,
+                                Err(Some(Wait::Release)) => {
+                                    released.wait(q).unwrap_or_else(PoisonError::into_inner)
+                                }
+                                Err(Some(Wait::For(wait))) => {
+                                    released
+                                        .wait_timeout(q, wait)
+                                        .unwrap_or_else(PoisonError::into_inner)
+                                        .0
+                                }
+                            };
+                        };
+                        drop(q);
+
+                        let _ = done_tx.send(Message::Started(i));
+                        let start = Instant::now();
+                        // the duration is in seconds
+                        let span = info_span!(
+                            "package",
+                            package = %packages[i].name,
+                            outcome = field::Empty,
+                            duration = field::Empty
+                        );
+                        let (value, outcome) = span.in_scope(|| f(&packages[i]));
+                        let elapsed = start.elapsed();
+                        span.record("outcome", field::display(outcome));
+                        span.record("duration", elapsed.as_secs_f64());
+                        drop(span);
+
+                        // released under the queue's lock, so no worker misses it between
+                        // finding every package waiting and starting to wait
+                        let q = lock(queue);
+                        drop(admission);
+                        released.notify_all();
+                        drop(q);
+
+                        let _ = done_tx.send(Message::Finished(i, value, outcome, elapsed));
+                    }
+                });
+            }
+            drop(done_tx);
+
+            for message in done_rx {
+                match message {
+                    Message::Started(i) => {
+                        progress.in_flight += 1;
+                        self.observer
+                            .event(&Event::Started(&packages[i]), &progress);
+                    }
+                    Message::Finished(i, value, outcome, elapsed) => {
+                        progress.in_flight -= 1;
+                        progress.completed += 1;
+                        progress.failed += usize::from(outcome == Outcome::Failed);
+                        let event = Event::Finished {
+                            package: &packages[i],
+                            outcome,
+                            elapsed,
+                        };
+                        self.observer.event(&event, &progress);
+                        results[i] = Some(Done { value, elapsed });
+                    }
+                }
+            }
+        });
+
+        results
+    }
+}
@@ -0,0 +1,150 @@
+// package/fetcher.rs
+//
+// Fetchers retrieve the raw version string for a channel. The channel's `fetcher` field selects
+// which one is used, and `PackageChannel::fetch` handles trimming and validation on top of it.
+
+use color_eyre::Result;
+use color_eyre::eyre::{ContextCompat, WrapErr, bail};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+use super::{Package, PackageChannel};
+use crate::context::Context;
+use crate::utils::ver::{self, Version};
+
+pub trait Fetcher: Send + Sync {
+    /// Retrieve the raw version string for a channel
+    ///
+    /// # Errors
+    ///
+    /// Fails if the version can't be retrieved.
+    fn fetch(&self, ctx: &Context, package: &Package, channel: &PackageChannel) -> Result<String>;
+}
+
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FetcherKind {
+    /// Evaluate `fetch` as a pipeline with the shell library sourced
+    #[default]
+    Shell,
+    /// List the upstream's refs with git, picking the latest tag matching `expected`, or `HEAD`
+    /// for commit channels
+    Git,
+    /// Download the upstream as JSON, selecting the value at the JSON pointer in `fetch`
+    Json,
+}
+
+/// Dispatches to the fetcher selected by each channel
+pub struct Dispatcher;
+
+impl Fetcher for Dispatcher {
+    fn fetch(&self, ctx: &Context, package: &Package, channel: &PackageChannel) -> Result<String> {
+        match channel.fetcher {
+            FetcherKind::Shell => ShellFetcher.fetch(ctx, package, channel),
+            FetcherKind::Git => GitFetcher.fetch(ctx, package, channel),
+            FetcherKind::Json => JsonFetcher.fetch(ctx, package, channel),
+        }
+    }
+}
+
+pub struct ShellFetcher;
+
+impl Fetcher for ShellFetcher {
+    fn fetch(&self, ctx: &Context, package: &Package, channel: &PackageChannel) -> Result<String> {
+        let fetch = format!(". {} && {}", ctx.shlib.display(), channel.fetch);
+        channel.cmd(ctx, package, &["bash", "-c", &fetch])
+    }
+}
+
+pub struct GitFetcher;
+
+impl Fetcher for GitFetcher {
+    fn fetch(&self, ctx: &Context, package: &Package, channel: &PackageChannel) -> Result<String> {
+        let upstream = channel.upstream(package);
+        let refs = channel.cmd(ctx, package, &["git", "ls-remote", "-q", &upstream])?;
+        let refs = refs
+            .lines()
+            .filter_map(|l| l.split_once('\t'))
+            .collect::<Vec<_>>();
+
+        if channel.name == "commit" {
+            return refs
+                .iter()
+                .find(|(_, r)| *r == "HEAD")
+                .or_else(|| refs.first())
+                .map(|(sha, _)| (*sha).to_string())
+                .wrap_err("No refs found");
+        }
+
+        let re = channel.expected_regex()?;
+        refs.iter()
+            .filter_map(|(_, r)| r.strip_prefix("refs/tags/"))
+            .filter(|t| !t.ends_with("^{}"))
+            .map(|t| {
+                let mut v = Version::new(t.to_string());
+                v.trim(package);
+                v.fmt
+            })
+            .filter(|v| re.as_ref().is_none_or(|re| re.is_match(v)))
+            .max_by(|a, b| ver::compare(a, b))
+            .wrap_err("No matching tags found")
+    }
+}
+
+pub struct JsonFetcher;
+
+impl Fetcher for JsonFetcher {
+    fn fetch(&self, ctx: &Context, package: &Package, channel: &PackageChannel) -> Result<String> {
+        let upstream = channel.upstream(package);
+        let body = channel.cmd(
+            ctx,
+            package,
+            &[
+                "curl",
+                "-fsSL",
+                "--retry",
+                "3",
+                "--connect-timeout",
+                "16",
+                "--max-time",
+                "128",
+                &upstream,
+            ],
+        )?;
+
+        let json: Value = serde_json::from_str(&body)
+            .wrap_err_with(|| format!("Invalid JSON from {upstream}"))?;
+
+        match json.pointer(&channel.fetch) {
+            Some(Value::String(s)) => Ok(s.clone()),
+            Some(Value::Number(n)) => Ok(n.to_string()),
+            Some(_) => bail!("Value at '{}' is not a string or number", channel.fetch),
+            None => bail!("Nothing at '{}' in {upstream}", channel.fetch),
+        }
+    }
+}
+
+/// Test double returning canned versions keyed by "package:channel"
+#[derive(Default)]
+pub struct MockFetcher {
+    pub versions: HashMap<String, String>,
+}
+
+impl MockFetcher {
+    #[must_use]
+    pub fn with(mut self, package: &str, channel: &str, version: &str) -> Self {
+        self.versions
+            .insert(format!("{package}:{channel}"), version.to_string());
+        self
+    }
+}
+
+impl Fetcher for MockFetcher {
+    fn fetch(&self, _ctx: &Context, package: &Package, channel: &PackageChannel) -> Result<String> {
+        self.versions
+            .get(&format!("{}:{}", package.name, channel.name))
+            .cloned()
+            .wrap_err("No mock version")
+    }
+}
@@ -0,0 +1,269 @@
+// package/formats.rs
+//
+// Aggregate output formats. Each renders the same `PackageVersions` data into `p/ALL.<format>`.
+
+use clap::ValueEnum;
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use super::{AllJson, PackageVersions};
+use crate::context::Context;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// An array of packages
+    Json,
+    /// Tab-separated package, channel, and version
+    Txt,
+    /// An array of package tables
+    Toml,
+    /// A row for each channel, with a header
+    Csv,
+    /// A package per line
+    Ndjson,
+    /// A database with packages, channels, and history tables
+    Sqlite,
+}
+
+impl Format {
+    pub const DEFAULT: [Self; 2] = [Self::Json, Self::Txt];
+
+    #[must_use]
+    pub fn file_name(self) -> String {
+        format!("ALL.{self}")
+    }
+
+    /// Render every package's versions in this format
+    ///
+    /// # Errors
+    ///
+    /// Fails if the versions can't be serialized.
+    pub fn render(self, ctx: &Context, all: &[PackageVersions]) -> Result<Vec<u8>> {
+        let rendered = match self {
+            Self::Json if ctx.config.output.schema >= 3 => {
+                serde_json::to_string_pretty(&AllJson {
+                    schema_version: ctx.config.output.schema,
+                    packages: all.to_vec(),
+                })?
+            }
+            Self::Json => serde_json::to_string_pretty(all)?,
+            Self::Txt => txt(all),
+            Self::Toml => toml::to_string_pretty(&TomlAll { packages: all })?,
+            Self::Csv => return csv(all),
+            Self::Ndjson => ndjson(all)?,
+            Self::Sqlite => return sqlite(ctx, all),
+        };
+
+        Ok(rendered.into_bytes())
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Json => "json",
+            Self::Txt => "txt",
+            Self::Toml => "toml",
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+            Self::Sqlite => "sqlite",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// TOML has no top-level arrays, so packages are nested under a key
+#[derive(Serialize)]
+struct TomlAll<'a> {
+    packages: &'a [PackageVersions],
+}
+
+#[must_use]
+pub fn txt(all: &[PackageVersions]) -> String {
+    let mut alltxt = String::new();
+    for p in all {
+        for c in &p.versions {
+            alltxt = format!("{alltxt}{}\t{}\t{}\n", p.package, c.channel, c.version);
+        }
+    }
+    alltxt
+}
+
+fn csv(all: &[PackageVersions]) -> Result<Vec<u8>> {
+    let mut w = csv::Writer::from_writer(vec![]);
+    w.write_record([
+        "package",
+        "channel",
+        "version",
+        "frozen",
+        "fetched_at",
+        "changed_at",
+        "source",
+        "status",
+        "raw",
+    ])?;
+
+    for p in all {
+        for c in &p.versions {
+            let status = c.status.map(|s| s.to_string());
+            w.write_record([
+                p.package.as_str(),
+                &c.channel,
+                &c.version,
+                if c.frozen { "true" } else { "false" },
+                c.fetched_at.as_deref().unwrap_or_default(),
+                c.changed_at.as_deref().unwrap_or_default(),
+                c.source.as_deref().unwrap_or_default(),
+                status.as_deref().unwrap_or_default(),
+                c.raw.as_deref().unwrap_or_default(),
+            ])?;
+        }
+    }
+
+    w.into_inner().wrap_err("Failed to write CSV")
+}
+
+fn ndjson(all: &[PackageVersions]) -> Result<String> {
+    let mut allndjson = String::new();
+    for p in all {
+        allndjson += &serde_json::to_string(p)?;
+        allndjson.push('\n');
+    }
+    Ok(allndjson)
+}
+
+const SQLITE_SCHEMA: &str = "
+CREATE TABLE packages (
+    name TEXT PRIMARY KEY
+);
+
+CREATE TABLE channels (
+    package TEXT NOT NULL REFERENCES packages (name),
+    channel TEXT NOT NULL,
+    version TEXT NOT NULL,
+    frozen INTEGER NOT NULL,
+    fetched_at TEXT,
+    changed_at TEXT,
+    source TEXT,
+    status TEXT,
+    raw TEXT,
+    PRIMARY KEY (package, channel)
+);
+
+CREATE TABLE history (
+    package TEXT NOT NULL,
+    channel TEXT NOT NULL,
+    version TEXT NOT NULL,
+    seen_at TEXT NOT NULL
+);
+";
+
+/// A version a channel has had, as recorded in the history table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub package: String,
+    pub channel: String,
+    pub version: String,
+    /// When the version was first seen, in RFC 3339
+    pub seen_at: String,
+}
+
+/// Read the history table from a database written by `--format sqlite`, oldest first
+///
+/// # Errors
+///
+/// Fails if the database can't be opened or queried.
+pub fn read_history(path: &Path) -> Result<Vec<Change>> {
+    let conn =
+        Connection::open(path).wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+    let mut stmt =
+        conn.prepare("SELECT package, channel, version, seen_at FROM history ORDER BY rowid")?;
+    let history = stmt
+        .query_map([], |r| {
+            Ok(Change {
+                package: r.get(0)?,
+                channel: r.get(1)?,
+                version: r.get(2)?,
+                seen_at: r.get(3)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(history)
+}
+
+/// Build the database in the cache, carrying history forward from the previous one
+///
+/// A history row is added whenever a channel's version differs from its latest one.
+fn sqlite(ctx: &Context, all: &[PackageVersions]) -> Result<Vec<u8>> {
+    let previous = ctx.packages_dir().join(Format::Sqlite.file_name());
+    let path = ctx.cache.join("ALL.sqlite.tmp");
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+
+    let res = build_sqlite(&path, &previous, all);
+    let bytes = res.and_then(|()| fs::read(&path).wrap_err("Failed to read built database"));
+    let _ = fs::remove_file(&path);
+    bytes
+}
+
+fn build_sqlite(path: &Path, previous: &Path, all: &[PackageVersions]) -> Result<()> {
+    let mut conn =
+        Connection::open(path).wrap_err_with(|| format!("Failed to create {}", path.display()))?;
+    conn.execute_batch(SQLITE_SCHEMA)?;
+
+    if previous.exists() {
+        conn.execute("ATTACH DATABASE ?1 AS old", [previous.to_string_lossy()])?;
+        conn.execute(
+            "INSERT INTO history SELECT package, channel, version, seen_at FROM old.history ORDER BY rowid",
+            [],
+        )
+        .wrap_err_with(|| format!("Failed to read history from {}", previous.display()))?;
+        conn.execute("DETACH DATABASE old", [])?;
+    }
+
+    let now = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+    let tx = conn.transaction()?;
+    for p in all {
+        tx.execute("INSERT INTO packages (name) VALUES (?1)", [&p.package])?;
+        for c in &p.versions {
+            tx.execute(
+                "INSERT INTO channels VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    p.package,
+                    c.channel,
+                    c.version,
+                    c.frozen,
+                    c.fetched_at,
+                    c.changed_at,
+                    c.source,
+                    c.status.map(|s| s.to_string()),
+                    c.raw,
+                ],
+            )?;
+        }
+    }
+
+    tx.execute(
+        "INSERT INTO history (package, channel, version, seen_at)
+        SELECT c.package, c.channel, c.version, COALESCE(c.changed_at, c.fetched_at, ?1)
+        FROM channels c
+        WHERE c.version IS NOT (
+            SELECT h.version FROM history h
+            WHERE h.package = c.package AND h.channel = c.channel
+            ORDER BY h.rowid DESC LIMIT 1
+        )",
+        [now],
+    )?;
+    tx.commit()?;
+
+    conn.close().map_err(|(_, e)| e)?;
+    Ok(())
+}
@@ -1,30 +1,36 @@
 // package/mod.rs
 
 pub mod bulk;
+pub mod engine;
+pub mod fetcher;
+pub mod formats;
+pub mod throttle;
 
 use color_eyre::Result;
 use color_eyre::eyre::bail;
 use rand::random_range;
 use regex::Regex;
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fmt::Debug;
 use std::fmt::Write;
+use std::fmt::{self, Debug, Display};
 use std::fs;
 use std::hash::Hash;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::str::FromStr;
-use tracing::{debug, error, info};
-
-use crate::utils::str::basename;
-use crate::NO_CACHE;
-use crate::SHLIB_PATH;
-use crate::VAGRANT_CACHE;
-use crate::VAGRANT_ROOT;
-use crate::args::ARGS;
-use crate::utils::cmd::cmd;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, error, field, info, info_span, warn};
+
+use self::fetcher::{Fetcher, FetcherKind};
+use crate::context::Context;
+use crate::record::{self, Recording};
+use crate::utils::cmd::{check, run};
+use crate::utils::duration;
 use crate::utils::float::defloat;
+use crate::utils::fs::Transaction;
 use crate::utils::shortform::{get_longform, get_shortform};
+use crate::utils::str::basename;
 use crate::utils::ver::Version;
 
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
@@ -50,24 +56,79 @@ impl PartialOrd for Package {
 pub struct PackageConfig {
     pub upstream: String,
     pub chance: f64,
+    /// Check the package when its last successful check is older than this, instead of by chance
+    #[serde(deserialize_with = "duration::deserialize_option")]
+    pub interval: Option<Duration>,
+    /// Arbitrary labels for grouping packages
+    pub tags: Vec<String>,
     pub channels: Vec<PackageChannel>,
 }
 
-/// Struct to be used when serializing into p/ALL
-#[derive(Debug, Serialize, Clone)]
+/// A package's versions, as an entry of `ALL.json`
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct PackageVersions {
     pub package: String,
     pub versions: Vec<VersionChannel>,
 }
 
+impl PackageVersions {
+    #[must_use]
+    pub fn new(package: &str, versions: Vec<VersionChannel>) -> Self {
+        Self {
+            package: package.to_string(),
+            versions,
+        }
+    }
+}
+
+/// `versions.json` from schema version 3, a package's `ALL.json` entry marked with its schema
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct VersionsJson {
+    #[schemars(schema_with = "schema_version")]
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub entry: PackageVersions,
+}
+
+/// `ALL.json` from schema version 3, with the schema marked once rather than on every entry
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AllJson {
+    #[schemars(schema_with = "schema_version")]
+    pub schema_version: u32,
+    pub packages: Vec<PackageVersions>,
+}
+
+/// Pin `schema_version` to the current schema, so consumers can reject any other
+fn schema_version(_: &mut SchemaGenerator) -> Schema {
+    json_schema!({
+        "description": "The schema version the file was written with",
+        "type": "integer",
+        "const": SCHEMA_VERSION,
+    })
+}
+
+/// `versions.json` as written by any schema version
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum VersionsFile {
+    /// Schema versions 1 and 2 write a bare array
+    Bare(Vec<VersionChannel>),
+    Versioned(VersionsJson),
+}
+
 #[derive(Hash, PartialEq, Eq, Debug, Deserialize, Clone)]
 #[serde(default)]
 pub struct PackageChannel {
     pub name: String,
     pub enabled: bool,
     pub upstream: Option<String>,
+    pub fetcher: FetcherKind,
     pub fetch: String,
     pub expected: Option<String>,
+    /// Carry the previous value forward instead of fetching
+    pub frozen: bool,
+    /// Refuse versions older than the previous value [default: true for release and unstable]
+    pub monotonic: Option<bool>,
     // TODO: Consider adding per-channel chances
 }
 
@@ -77,34 +138,76 @@ impl Default for PackageChannel {
             name: String::new(),
             enabled: true,
             upstream: None,
+            fetcher: FetcherKind::default(),
             fetch: String::new(),
             expected: None,
+            frozen: false,
+            monotonic: None,
         }
     }
 }
 
 impl PackageChannel {
-    pub fn cmd(&self, package: &Package, command: &[&str]) -> Result<String> {
-        let package_root = Package::dir(&package.name);
+    /// Whether the channel's versions may only go forwards
+    #[must_use]
+    pub fn is_monotonic(&self) -> bool {
+        self.monotonic
+            .unwrap_or(matches!(self.name.as_str(), "release" | "unstable"))
+    }
 
-        let Some(vagrant_root) = VAGRANT_ROOT.to_str() else {
-            bail!("Invalid Unicode in {}", VAGRANT_ROOT.display());
+    /// Resolve the upstream for this channel, falling back to the package's
+    #[must_use]
+    pub fn upstream(&self, package: &Package) -> String {
+        get_longform(self.upstream.as_ref().unwrap_or(&package.config.upstream))
+    }
+
+    /// Compile the expected regex, if the channel has one
+    ///
+    /// # Errors
+    ///
+    /// Fails if the regex is invalid.
+    pub fn expected_regex(&self) -> Result<Option<Regex>> {
+        let Some(re) = &self.expected else {
+            return Ok(None);
         };
 
-        let Some(vagrant_cache) = VAGRANT_CACHE.to_str() else {
-            bail!("Invalid Unicode in {}", VAGRANT_CACHE.display());
+        match Regex::from_str(re) {
+            Ok(re) => Ok(Some(re)),
+            Err(e) => {
+                error!("Invalid expected regex '{re}': {e}");
+                bail!("Invalid expected regex");
+            }
+        }
+    }
+
+    /// Run a command for this channel from the package's directory, with the shell library's environment
+    ///
+    /// # Errors
+    ///
+    /// Fails if the command fails or its output doesn't pass [`check`].
+    pub fn cmd(&self, ctx: &Context, package: &Package, command: &[&str]) -> Result<String> {
+        let package_root = Package::dir(ctx, &package.name);
+
+        let Some(vagrant_root) = ctx.root.to_str() else {
+            bail!("Invalid Unicode in {}", ctx.root.display());
+        };
+
+        let Some(vagrant_cache) = ctx.cache.to_str() else {
+            bail!("Invalid Unicode in {}", ctx.cache.display());
         };
 
-        let Some(shlib_path) = SHLIB_PATH.to_str() else {
-            bail!("Invalid Unicode in {}", SHLIB_PATH.display());
+        let Some(shlib_path) = ctx.shlib.to_str() else {
+            bail!("Invalid Unicode in {}", ctx.shlib.display());
         };
 
-        let no_cache = NO_CACHE.to_string();
+        let no_cache = ctx.args.no_cache.to_string();
 
-        let upstream = get_longform(self.upstream.as_ref().unwrap_or(&package.config.upstream));
+        let upstream = self.upstream(package);
         let shortform = get_shortform(&upstream);
 
-        let env = HashMap::from([
+        let cache_ttls = ctx.config.cache.env();
+
+        let mut env = HashMap::from([
             ("GIT_TERMINAL_PROMPT", "false"),
             ("PACKAGE_ROOT", &package_root),
             ("VAGRANT_ROOT", vagrant_root),
@@ -116,39 +219,61 @@ impl PackageChannel {
             ("upstream", &upstream),
             ("shortform", &shortform),
         ]);
+        env.extend(cache_ttls.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+
+        if let Some(dir) = &ctx.args.replay {
+            let recording = Recording::load(dir, &package.name, &self.name)?;
+            if !recording.matches(self, &env) {
+                warn!(
+                    "Recording for {}:{} was made with a different fetch or environment",
+                    package.name, self.name
+                );
+            }
+            return check(recording.output);
+        }
 
-        cmd(command, env, &package_root)
-    }
+        let output = run(command, env.clone(), &package_root)?;
+
+        if let Some(dir) = &ctx.args.record {
+            Recording::new(package, self, &env, output.clone()).save(dir)?;
+        }
 
-    pub fn fetch(&self, package: &Package) -> Result<String> {
-        let fetch = format!(". {} && {}", SHLIB_PATH.display(), self.fetch);
-        let command = ["bash", "-c", &fetch];
+        check(output)
+    }
 
-        let ver = match self.cmd(package, &command) {
+    /// Fetch and validate the version for this channel
+    ///
+    /// # Errors
+    ///
+    /// Fails if the fetch fails, or its version is empty or doesn't match what's expected.
+    pub fn fetch(
+        &self,
+        ctx: &Context,
+        package: &Package,
+        fetcher: &dyn Fetcher,
+    ) -> Result<Version> {
+        let ver = match fetcher.fetch(ctx, package, self) {
             Err(e) => bail!("Failed to fetch version: {e}"),
             Ok(v) => v,
         };
 
+        // fetchers other than commands can succeed with nothing, which would otherwise be written
+        if ver.trim().is_empty() {
+            error!("Fetched an empty version");
+            bail!("Fetched an empty version");
+        }
+
         let mut version = Version::new(ver);
         version.trim(package);
-        let v = version.fmt;
-
-        if let Some(re) = &self.expected {
-            let re = match Regex::from_str(re) {
-                Ok(re) => re,
-                Err(e) => {
-                    error!("Invalid expected regex '{re}': {e}");
-                    bail!("Invalid expected regex");
-                }
-            };
 
-            if !re.is_match(&v) {
-                error!("Version '{v}' does not match expected '{re}'");
-                bail!("Version does not match expected");
-            }
+        if let Some(re) = self.expected_regex()?
+            && !re.is_match(&version.fmt)
+        {
+            error!("Version '{}' does not match expected '{re}'", version.fmt);
+            bail!("Version does not match expected");
         }
 
-        Ok(v)
+        Ok(version)
     }
 }
 
@@ -156,6 +281,8 @@ impl Hash for PackageConfig {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.upstream.hash(state);
         defloat(self.chance).hash(state);
+        self.interval.hash(state);
+        self.tags.hash(state);
         self.channels.hash(state);
     }
 }
@@ -164,16 +291,121 @@ impl PartialEq for PackageConfig {
     fn eq(&self, other: &Self) -> bool {
         self.upstream == other.upstream
             && (self.chance - other.chance).abs() < 0.01
+            && self.interval == other.interval
+            && self.tags == other.tags
             && self.channels == other.channels
     }
 }
 
 impl Eq for PackageConfig {}
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// The version of the `versions.json` and `ALL.json` schema
+///
+/// - 1: `channel`, `version`, and `frozen`
+/// - 2: adds the optional `fetched_at`, `changed_at`, `source`, `status`, and `raw`
+/// - 3: adds `schema_version`, `versions.json` takes the shape of an `ALL.json` entry, and
+///   `ALL.json` nests its entries under `packages`
+pub const SCHEMA_VERSION: u32 = 3;
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct VersionChannel {
     pub channel: String,
     pub version: String,
+    /// Whether the version was carried forward from a frozen channel
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub frozen: bool,
+    /// When the version was last fetched, in RFC 3339
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetched_at: Option<String>,
+    /// When the version last changed, in RFC 3339
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changed_at: Option<String>,
+    /// The resolved upstream the version was fetched from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    /// The version as fetched, before trimming
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+impl VersionChannel {
+    /// Drop everything not in schema version 1
+    pub fn strip_metadata(&mut self) {
+        self.fetched_at = None;
+        self.changed_at = None;
+        self.source = None;
+        self.status = None;
+        self.raw = None;
+    }
+}
+
+/// How a version was arrived at in the latest run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Fetched and verified
+    Fresh,
+    /// The channel failed to fetch or went backwards, so its previous value was kept
+    Fallback,
+    /// The channel wasn't fetched, as its package was skipped or the channel is frozen
+    Skipped,
+    /// The package failed to fetch, so its previous values were kept
+    Failed,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Fresh => "fresh",
+            Self::Fallback => "fallback",
+            Self::Skipped => "skipped",
+            Self::Failed => "failed",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// Versions fetched for a package, along with the channels that failed
+#[derive(Debug, Default)]
+pub struct Fetched {
+    pub versions: Vec<VersionChannel>,
+    /// Channels that failed to fetch, reusing their previous value if there was one
+    pub failed: Vec<String>,
+}
+
+/// A channel whose version differs from the one previously written
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionChange {
+    pub package: String,
+    pub channel: String,
+    /// The previous version, or none if the channel is new
+    pub old: Option<String>,
+    pub new: String,
+    /// The package's tags
+    pub tags: Vec<String>,
+}
+
+impl VersionChange {
+    /// The channels in `new` whose versions differ from, or are missing in, `old`
+    #[must_use]
+    pub fn between(package: &Package, old: &[VersionChannel], new: &[VersionChannel]) -> Vec<Self> {
+        new.iter()
+            .filter_map(|c| {
+                let previous = old.iter().find(|o| o.channel == c.channel);
+                previous
+                    .is_none_or(|o| o.version != c.version)
+                    .then(|| Self {
+                        package: package.name.clone(),
+                        channel: c.channel.clone(),
+                        old: previous.map(|o| o.version.clone()),
+                        new: c.version.clone(),
+                        tags: package.config.tags.clone(),
+                    })
+            })
+            .collect()
+    }
 }
 
 impl Default for PackageConfig {
@@ -181,6 +413,8 @@ impl Default for PackageConfig {
         Self {
             upstream: String::new(),
             chance: 1.0,
+            interval: None,
+            tags: vec![],
             channels: vec![],
         }
     }
@@ -214,29 +448,59 @@ impl UpstreamType {
 }
 
 impl Package {
-    pub fn from_name<S: Into<String>>(name: S) -> Result<Self> {
+    /// Load a package from its config under `p/`
+    ///
+    /// # Errors
+    ///
+    /// Fails if the config can't be read or is invalid.
+    pub fn from_name<S: Into<String>>(ctx: &Context, name: S) -> Result<Self> {
         let name = name.into();
-        let config_path = Path::new(Self::dir(&name).as_str()).join("config");
+        let config_path = ctx.packages_dir().join(&name).join("config");
 
         let raw = fs::read_to_string(config_path)?;
         let config: PackageConfig = toml::from_str(&raw)?;
 
         let mut package = Self { name, config };
-        package.set_defaults();
+        package.set_defaults()?;
 
         Ok(package)
     }
 
     /// Retrieve the directory for a package
-    pub fn dir<S: AsRef<str>>(name: S) -> String {
-        format!("{}/p/{}", VAGRANT_ROOT.display(), name.as_ref())
+    pub fn dir<S: AsRef<str>>(ctx: &Context, name: S) -> String {
+        format!("{}/{}", ctx.packages_dir().display(), name.as_ref())
     }
 
+    #[must_use]
     pub fn get_channel(&self, name: &str) -> Option<&PackageChannel> {
         self.config.channels.iter().find(|c| c.name == name)
     }
 
-    pub fn set_defaults(&mut self) {
+    /// Whether a channel is configured and enabled
+    #[must_use]
+    pub fn is_live(&self, channel: &str) -> bool {
+        self.get_channel(channel).is_some_and(|c| c.enabled)
+    }
+
+    /// Whether a channel is configured as frozen
+    #[must_use]
+    pub fn is_frozen(&self, channel: &str) -> bool {
+        self.get_channel(channel).is_some_and(|c| c.frozen)
+    }
+
+    /// Whether a channel is configured as monotonic
+    #[must_use]
+    pub fn is_monotonic(&self, channel: &str) -> bool {
+        self.get_channel(channel)
+            .is_some_and(PackageChannel::is_monotonic)
+    }
+
+    /// Fill in the default upstream, and each channel's default fetch and expected pattern
+    ///
+    /// # Errors
+    ///
+    /// Fails if a channel has no fetch or expected pattern and none can be inferred.
+    pub fn set_defaults(&mut self) -> Result<()> {
         if self.config.upstream.is_empty() {
             self.config.upstream = format!("{n}/{n}", n = basename(&self.name));
         }
@@ -245,7 +509,7 @@ impl Package {
             let upstream = channel.upstream.as_ref().unwrap_or(&self.config.upstream);
             let ut = UpstreamType::from_str(upstream);
 
-            if channel.fetch.is_empty() {
+            if channel.fetch.is_empty() && channel.fetcher == FetcherKind::Shell {
                 channel.fetch = match (ut, channel.name.as_str()) {
                     (UpstreamType::Arch, "release") => "archver".into(),
 
@@ -259,35 +523,38 @@ impl Package {
                     (UpstreamType::Git, "unstable") => "defgitunstable".into(),
                     (UpstreamType::Git, "commit") => "defgitcommit".into(),
 
-                    _ => panic!("Invalid config in {}: Missing fetch for {}", self.name, channel.name)
+                    _ => bail!(
+                        "Invalid config in {}: Missing fetch for {}",
+                        self.name,
+                        channel.name
+                    ),
                 }
             }
 
             if channel.expected.is_none() {
                 channel.expected = match channel.name.as_str() {
                     "release" => Some(r"^[0-9]+(\.[0-9]+)*$".into()),
-                    "unstable" => Some(r"^[0-9]+(\.[0-9]+)*-?(rc|alpha|beta|a|b|pre|dev)?[0-9]*$".into()),
+                    "unstable" => {
+                        Some(r"^[0-9]+(\.[0-9]+)*-?(rc|alpha|beta|a|b|pre|dev)?[0-9]*$".into())
+                    }
                     "commit" => Some(r"^[0-9a-f]{40}$".into()),
                     n if n.parse::<u64>().is_ok() => Some(format!(r"^{n}(\.[0-9]+)*$")),
 
-                    _ => panic!("Invalid config in {}: Missing expected for {}", self.name, channel.name)
+                    _ => bail!(
+                        "Invalid config in {}: Missing expected for {}",
+                        self.name,
+                        channel.name
+                    ),
                 }
             }
         }
-    }
 
-    pub fn has_fallback_versions(&self) -> bool {
-        let path = Path::new("p").join(&self.name).join("versions.json");
-        if !path.exists() {
-            return false;
-        }
+        Ok(())
+    }
 
-        let Ok(version_channels_str) = fs::read_to_string(path) else {
-            return false;
-        };
-        let Ok(version_channels) =
-            serde_json::from_str::<Vec<VersionChannel>>(&version_channels_str)
-        else {
+    #[must_use]
+    pub fn has_fallback_versions(&self, ctx: &Context) -> bool {
+        let Ok(Some(version_channels)) = self.read_previous_versions(ctx) else {
             return false;
         };
 
@@ -301,6 +568,7 @@ impl Package {
     }
 
     /// Used for log output only
+    #[must_use]
     pub fn format_fetched(&self, version_channels: &[VersionChannel]) -> String {
         let mut s = String::new();
         let _ = writeln!(&mut s, "Fetched versions for {}", self.name);
@@ -310,70 +578,347 @@ impl Package {
         s
     }
 
-    pub fn fetch(&self) -> Result<Vec<VersionChannel>> {
+    /// Whether the package should be checked, by its interval if it has one, or else by chance
+    #[must_use]
+    pub fn is_due(&self, ctx: &Context, now: SystemTime) -> bool {
+        let by_chance =
+            || self.config.chance >= 1.0 || random_range(0.0..=1.0) <= self.config.chance;
+        self.config.interval.map_or_else(by_chance, |interval| {
+            self.last_checked(ctx).is_none_or(|t| t + interval <= now)
+        })
+    }
+
+    /// When the package was last checked without any channel failing
+    #[must_use]
+    pub fn last_checked(&self, ctx: &Context) -> Option<SystemTime> {
+        let path = self.get_package_path(ctx).join("last_checked");
+        let raw = fs::read_to_string(path).ok()?;
+        humantime::parse_rfc3339(raw.trim()).ok()
+    }
+
+    /// Whether the package should be skipped this run, keeping its previous versions
+    ///
+    /// Decided by chance for packages without an interval, so it's asked once per run.
+    #[must_use]
+    pub fn is_skipped(&self, ctx: &Context) -> bool {
         // if fallback versions don't exist, or --guarantee is passed, guarantee a fetch
-        let should_guarantee = ARGS.guarantee || !self.has_fallback_versions();
+        let should_guarantee = ctx.args.guarantee || !self.has_fallback_versions(ctx);
 
-        if self.config.chance < 1.0
-            && !should_guarantee
-            && random_range(0.0..=1.0) > self.config.chance
-        {
-            bail!("Tails!")
+        // when replaying, reproduce the skips of the recorded run
+        ctx.args.replay.as_ref().map_or_else(
+            || !should_guarantee && !self.is_due(ctx, SystemTime::now()),
+            |dir| !record::has_package(dir, self),
+        )
+    }
+
+    /// Fetch every enabled channel, recording the package if `--record` is passed
+    ///
+    /// # Errors
+    ///
+    /// Fails if the package can't be marked in the recording.
+    pub fn fetch(&self, ctx: &Context, fetcher: &dyn Fetcher) -> Result<Fetched> {
+        if let Some(dir) = &ctx.args.record {
+            record::mark_package(dir, self)?;
         }
 
-        let mut version_channels = vec![];
+        let outcome = self.fetch_channels(ctx, fetcher);
+
+        info!("{}", self.format_fetched(&outcome.versions));
+        debug!(
+            "Versions as JSON: {}",
+            serde_json::to_string_pretty(&outcome.versions)?
+        );
+
+        Ok(outcome)
+    }
+
+    /// Fetch every enabled channel
+    ///
+    /// Channels that fail to fetch fall back to their previous value, and frozen channels aren't
+    /// fetched at all, carrying theirs forward. Either is omitted if there is no previous value.
+    pub fn fetch_channels(&self, ctx: &Context, fetcher: &dyn Fetcher) -> Fetched {
+        let previous = self.read_versions(ctx).unwrap_or_default();
+        let previous = |name: &str| previous.iter().find(|vc| vc.channel == name);
+
+        let mut outcome = Fetched::default();
         for channel in &self.config.channels {
-            if channel.enabled {
-                version_channels.push(VersionChannel {
-                    channel: channel.name.clone(),
-                    version: channel.fetch(self)?,
+            if !channel.enabled {
+                continue;
+            }
+
+            // the duration is in seconds
+            let span = info_span!(
+                "channel",
+                channel = %channel.name,
+                outcome = field::Empty,
+                duration = field::Empty
+            );
+            let _enter = span.enter();
+            let start = Instant::now();
+
+            let status = if channel.frozen {
+                Status::Skipped
+            } else {
+                match channel.fetch(ctx, self, fetcher) {
+                    Ok(version) => {
+                        let now = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+                        let changed_at = match previous(&channel.name) {
+                            Some(vc) if vc.version == version.fmt => vc.changed_at.clone(),
+                            _ => Some(now.clone()),
+                        };
+
+                        outcome.versions.push(VersionChannel {
+                            channel: channel.name.clone(),
+                            version: version.fmt,
+                            fetched_at: Some(now),
+                            changed_at,
+                            source: Some(channel.upstream(self)),
+                            status: Some(Status::Fresh),
+                            raw: Some(version.raw.trim().to_string()),
+                            ..Default::default()
+                        });
+                        Status::Fresh
+                    }
+                    Err(e) => {
+                        error!("Failed to fetch {}:{}: {e}", self.name, channel.name);
+                        outcome.failed.push(channel.name.clone());
+                        Status::Fallback
+                    }
+                }
+            };
+
+            span.record("outcome", field::display(status));
+            span.record("duration", start.elapsed().as_secs_f64());
+            if status == Status::Fresh {
+                continue;
+            }
+
+            if let Some(vc) = previous(&channel.name) {
+                outcome.versions.push(VersionChannel {
+                    frozen: channel.frozen,
+                    status: Some(status),
+                    ..vc.clone()
                 });
+            } else {
+                warn!(
+                    "No previous value for {}:{}, omitting it",
+                    self.name, channel.name
+                );
             }
         }
 
-        info!("{}", self.format_fetched(&version_channels));
-        debug!(
-            "Versions as JSON: {}",
-            serde_json::to_string_pretty(&version_channels)?
-        );
+        outcome
+    }
 
-        Ok(version_channels)
+    #[must_use]
+    pub fn get_package_path(&self, ctx: &Context) -> PathBuf {
+        ctx.packages_dir().join(&self.name)
     }
 
-    pub fn get_package_path(&self) -> PathBuf {
-        Path::new("p").join(&self.name)
+    /// Render `versions.json` in the configured schema version
+    ///
+    /// # Errors
+    ///
+    /// Fails if the versions can't be serialized.
+    pub fn render_versions_json(
+        &self,
+        ctx: &Context,
+        version_channels: &[VersionChannel],
+    ) -> Result<String> {
+        let json = if ctx.config.output.schema >= 3 {
+            serde_json::to_string_pretty(&VersionsJson {
+                schema_version: ctx.config.output.schema,
+                entry: PackageVersions::new(&self.name, version_channels.to_vec()),
+            })?
+        } else {
+            serde_json::to_string_pretty(&version_channels)?
+        };
+        Ok(json)
     }
 
-    /// Write version data for all version channels for all APIs
-    pub fn write_versions(&self, version_channels: Vec<VersionChannel>) -> Result<()> {
-        let path = self.get_package_path();
-        fs::write(
+    /// Stage version data for all version channels for all APIs
+    ///
+    /// # Errors
+    ///
+    /// Fails if the versions can't be rendered or staged.
+    pub fn stage_versions(
+        &self,
+        ctx: &Context,
+        txn: &mut Transaction,
+        version_channels: &[VersionChannel],
+    ) -> Result<()> {
+        let path = self.get_package_path(ctx);
+        txn.stage(
             path.join("versions.json"),
-            serde_json::to_string_pretty(&version_channels)?,
+            self.render_versions_json(ctx, version_channels)?,
         )?;
 
         let channels_dir = path.join("channels");
 
-        if !channels_dir.exists() {
-            fs::create_dir(&channels_dir)?;
-        }
-
         let mut versionstxt = String::new();
         for channel in version_channels {
-            fs::write(channels_dir.join(&channel.channel), &channel.version)?;
+            txn.stage(channels_dir.join(&channel.channel), &channel.version)?;
             versionstxt = format!("{versionstxt}{}\t{}\n", channel.channel, channel.version);
         }
 
-        fs::write(path.join("versions.txt"), versionstxt)?;
+        txn.stage(path.join("versions.txt"), versionstxt)?;
         Ok(())
     }
 
+    /// Stage the removal of channel files for channels that are no longer live
+    ///
+    /// Returns the names of the pruned channels.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the channels directory can't be read.
+    pub fn stage_prune(&self, ctx: &Context, txn: &mut Transaction) -> Result<Vec<String>> {
+        let channels_dir = self.get_package_path(ctx).join("channels");
+        if !channels_dir.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut pruned = vec![];
+        for entry in channels_dir.read_dir()?.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            // skip staged files
+            if name.starts_with('.') {
+                continue;
+            }
+
+            if !self.is_live(&name) {
+                txn.remove(entry.path());
+                pruned.push(name);
+            }
+        }
+
+        pruned.sort();
+        Ok(pruned)
+    }
+
+    /// Read version data if any has been written
+    ///
+    /// # Errors
+    ///
+    /// Fails if `versions.json` exists but can't be read.
+    pub fn read_previous_versions(&self, ctx: &Context) -> Result<Option<Vec<VersionChannel>>> {
+        if !self.get_package_path(ctx).join("versions.json").exists() {
+            return Ok(None);
+        }
+
+        self.read_versions(ctx).map(Some)
+    }
+
     /// Write version data for all version channels (reads from JSON API)
-    pub fn read_versions(&self) -> Result<Vec<VersionChannel>> {
-        let path = self.get_package_path().join("versions.json");
+    ///
+    /// # Errors
+    ///
+    /// Fails if `versions.json` can't be read or parsed.
+    pub fn read_versions(&self, ctx: &Context) -> Result<Vec<VersionChannel>> {
+        let path = self.get_package_path(ctx).join("versions.json");
         let json_str = fs::read_to_string(path)?;
+        parse_versions(&json_str)
+    }
+}
+
+/// Parse the contents of a `versions.json` written with any schema version
+///
+/// # Errors
+///
+/// Fails if the contents aren't valid versions.
+pub fn parse_versions(json: &str) -> Result<Vec<VersionChannel>> {
+    let version_channels = match serde_json::from_str(json)? {
+        VersionsFile::Bare(v) => v,
+        VersionsFile::Versioned(v) => v.entry.versions,
+    };
+    Ok(version_channels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fetcher::MockFetcher;
+    use super::*;
+    use crate::args::Args;
+    use pretty_assertions::assert_eq;
+
+    fn ctx() -> Context {
+        Context::new(Args {
+            root: Some(PathBuf::from("/nonexistent")),
+            ..Default::default()
+        })
+        .expect("context should form")
+    }
+
+    fn package(channels: &[(&str, bool)]) -> Package {
+        let mut package = Package {
+            name: "foo".into(),
+            config: PackageConfig {
+                channels: channels
+                    .iter()
+                    .map(|&(name, enabled)| PackageChannel {
+                        name: name.into(),
+                        enabled,
+                        ..Default::default()
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+        };
+        package
+            .set_defaults()
+            .expect("defaults should be set for known channels");
+        package
+    }
+
+    #[test]
+    fn fetch_channels_trims_enabled_channels() {
+        let package = package(&[("release", true), ("unstable", false)]);
+        let fetcher = MockFetcher::default()
+            .with("foo", "release", "v1.2.3\n")
+            .with("foo", "unstable", "1.3.0-rc1");
+
+        let outcome = package.fetch_channels(&ctx(), &fetcher);
+
+        assert_eq!(outcome.failed, Vec::<String>::new());
+        assert_eq!(outcome.versions.len(), 1);
+        assert_eq!(outcome.versions[0].channel, "release");
+        assert_eq!(outcome.versions[0].version, "1.2.3");
+    }
+
+    #[test]
+    fn fetch_channels_omits_frozen_channels_without_previous_values() {
+        let mut package = package(&[("release", true), ("commit", true)]);
+        package.config.channels[1].frozen = true;
+        let fetcher = MockFetcher::default().with("foo", "release", "1.0");
+
+        let outcome = package.fetch_channels(&ctx(), &fetcher);
+
+        assert_eq!(outcome.failed, Vec::<String>::new());
+        assert_eq!(outcome.versions.len(), 1);
+        assert_eq!(outcome.versions[0].channel, "release");
+    }
+
+    #[test]
+    fn fetch_channels_rejects_unexpected_versions() {
+        let package = package(&[("release", true)]);
+        let fetcher = MockFetcher::default().with("foo", "release", "garbage");
+
+        let outcome = package.fetch_channels(&ctx(), &fetcher);
+
+        assert_eq!(outcome.failed, ["release"]);
+        assert_eq!(outcome.versions.len(), 0);
+    }
+
+    #[test]
+    fn fetch_channels_keeps_successful_channels() {
+        let package = package(&[("release", true), ("commit", true)]);
+        let fetcher = MockFetcher::default().with("foo", "release", "1.0");
+
+        let outcome = package.fetch_channels(&ctx(), &fetcher);
 
-        let version_channels = serde_json::from_str(&json_str)?;
-        Ok(version_channels)
+        assert_eq!(outcome.failed, ["commit"]);
+        assert_eq!(outcome.versions.len(), 1);
+        assert_eq!(outcome.versions[0].version, "1.0");
     }
 }
@@ -0,0 +1,157 @@
+// package/throttle.rs
+//
+// Politeness towards upstreams. Packages are grouped by the hosts of their channels' resolved
+// upstreams, and each host can be limited to a number of packages fetching from it at once and a
+// minimum spacing between their starts. Limits are configured under `[hosts]` in `vagrant.toml`,
+// where `"*"` applies to every host not listed.
+//
+// The engine admits a package only once all of its hosts have room, and otherwise takes on other
+// packages in the meantime, so a capped host never holds up the rest.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tracing::trace;
+
+use super::Package;
+use crate::utils::duration;
+use crate::utils::str::host;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostLimit {
+    /// The most packages fetching from the host at once, or unlimited if unset
+    pub concurrency: Option<usize>,
+    /// The least time between the starts of two packages fetching from the host
+    #[serde(deserialize_with = "duration::deserialize")]
+    pub spacing: Duration,
+}
+
+#[derive(Default)]
+struct HostState {
+    in_flight: usize,
+    last_start: Option<Instant>,
+}
+
+/// Per-host limits, and the packages currently admitted under them
+#[derive(Default)]
+pub struct Hosts {
+    limits: HashMap<String, HostLimit>,
+    state: Mutex<HashMap<String, HostState>>,
+}
+
+/// Why a package can't be admitted yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wait {
+    /// One of its hosts is at its concurrency, until a package fetching from it finishes
+    Release,
+    /// One of its hosts was started on too recently, for this long
+    For(Duration),
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Hosts {
+    #[must_use]
+    pub fn new(limits: &HashMap<String, HostLimit>) -> Self {
+        Self {
+            limits: limits.clone(),
+            state: Mutex::default(),
+        }
+    }
+
+    fn limit(&self, host: &str) -> Option<&HostLimit> {
+        self.limits.get(host).or_else(|| self.limits.get("*"))
+    }
+
+    /// The limited hosts a package fetches from
+    fn hosts_of(&self, package: &Package) -> Vec<(String, &HostLimit)> {
+        let mut hosts = package
+            .config
+            .channels
+            .iter()
+            .filter(|c| c.enabled && !c.frozen)
+            .filter_map(|c| {
+                let upstream = c.upstream(package);
+                let host = host(&upstream)?.to_string();
+                let limit = self.limit(&host)?;
+                Some((host, limit))
+            })
+            .collect::<Vec<_>>();
+        hosts.sort_by(|a, b| a.0.cmp(&b.0));
+        hosts.dedup_by(|a, b| a.0 == b.0);
+        hosts
+    }
+
+    /// Count a package as fetching from each of its limited hosts until the admission is dropped,
+    /// or say what it's waiting on if any of them has no room
+    ///
+    /// # Errors
+    ///
+    /// Fails with what to wait on if the package can't be admitted yet.
+    pub fn try_admit(&self, package: &Package) -> Result<Admission<'_>, Wait> {
+        let hosts = self.hosts_of(package);
+        if hosts.is_empty() {
+            return Ok(Admission {
+                hosts: self,
+                taken: vec![],
+            });
+        }
+
+        let mut state = lock(&self.state);
+        let mut wait = Duration::ZERO;
+        for (host, limit) in &hosts {
+            let Some(s) = state.get(host) else { continue };
+            if limit.concurrency.is_some_and(|c| s.in_flight >= c.max(1)) {
+                trace!("Package '{}' waiting on {host}", package.name);
+                return Err(Wait::Release);
+            }
+            let left = s.last_start.map_or(Duration::ZERO, |t| {
+                limit.spacing.saturating_sub(t.elapsed())
+            });
+            wait = wait.max(left);
+        }
+
+        if !wait.is_zero() {
+            trace!("Package '{}' waiting {wait:?} on spacing", package.name);
+            return Err(Wait::For(wait));
+        }
+
+        let now = Instant::now();
+        let taken = hosts
+            .into_iter()
+            .map(|(host, _)| {
+                let s = state.entry(host.clone()).or_default();
+                s.in_flight += 1;
+                s.last_start = Some(now);
+                host
+            })
+            .collect();
+        drop(state);
+        Ok(Admission { hosts: self, taken })
+    }
+}
+
+/// A package's place on its hosts, given back when dropped
+pub struct Admission<'a> {
+    hosts: &'a Hosts,
+    taken: Vec<String>,
+}
+
+impl Drop for Admission<'_> {
+    fn drop(&mut self) {
+        if self.taken.is_empty() {
+            return;
+        }
+
+        let mut state = lock(&self.hosts.state);
+        for host in &self.taken {
+            if let Some(s) = state.get_mut(host) {
+                s.in_flight -= 1;
+            }
+        }
+    }
+}
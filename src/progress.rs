@@ -0,0 +1,151 @@
+// progress.rs
+//
+// A live status line for bulk runs. When stdout is a terminal, its last line shows how far through
+// the run is, and log lines are written above it through `Writer`, which clears the status line
+// and draws it again after each one. When stdout isn't a terminal, nothing is drawn, and progress
+// is left to the engine's log lines, so piped output stays plain.
+
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::package::engine::{Event, Log, Observer, Progress};
+
+/// Clears the line the cursor is on
+const CLEAR: &[u8] = b"\r\x1b[2K";
+
+/// The status line being shown, or empty if none is
+static LINE: Mutex<String> = Mutex::new(String::new());
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Writes to stdout beneath the status line
+pub struct Writer;
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let line = lock(&LINE).clone();
+        let mut out = io::stdout().lock();
+        if line.is_empty() {
+            return out.write(buf);
+        }
+
+        out.write_all(CLEAR)?;
+        out.write_all(buf)?;
+        if buf.ends_with(b"\n") {
+            out.write_all(line.as_bytes())?;
+        }
+        out.flush()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+/// The status line for a run, cut to `width` characters
+#[must_use]
+pub fn status(progress: &Progress, waiting_on: Option<&str>, width: usize) -> String {
+    let percent = (progress.completed * 100)
+        .checked_div(progress.total)
+        .unwrap_or(100);
+    let eta = progress.eta().map_or_else(
+        || "?".into(),
+        |eta| humantime::format_duration(eta).to_string(),
+    );
+    let mut line = format!(
+        "[ {}/{} {percent}% ] {} in flight, {} failed, {eta} left",
+        progress.completed, progress.total, progress.in_flight, progress.failed
+    );
+    if let Some(name) = waiting_on {
+        line = format!("{line}, waiting on {name}");
+    }
+    line.chars().take(width).collect()
+}
+
+/// The width of the terminal on stdout, if it is one and reports a width
+fn terminal_width() -> Option<usize> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ only writes a `winsize` through the pointer, which outlives the call
+    let res = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &raw mut size) };
+    (res == 0 && size.ws_col > 0).then_some(usize::from(size.ws_col))
+}
+
+/// Draws the status line as a run progresses, clearing it once dropped
+pub struct Bar {
+    /// Packages in flight, in the order they started
+    in_flight: Mutex<Vec<String>>,
+    width: usize,
+}
+
+impl Bar {
+    #[must_use]
+    pub fn new() -> Self {
+        // a line as wide as the terminal would wrap once the cursor reached its end
+        let width = terminal_width()
+            .or_else(|| env::var("COLUMNS").ok()?.parse().ok())
+            .unwrap_or(80)
+            .saturating_sub(1);
+        Self {
+            in_flight: Mutex::new(vec![]),
+            width,
+        }
+    }
+
+    fn draw(line: String) {
+        let mut shown = lock(&LINE);
+        let mut out = io::stdout().lock();
+        let _ = out.write_all(CLEAR);
+        let _ = out.write_all(line.as_bytes());
+        let _ = out.flush();
+        *shown = line;
+    }
+}
+
+impl Default for Bar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Observer for Bar {
+    fn event(&self, event: &Event<'_>, progress: &Progress) {
+        let line = {
+            let mut in_flight = lock(&self.in_flight);
+            match event {
+                Event::Started(package) => in_flight.push(package.name.clone()),
+                Event::Finished { package, .. } => in_flight.retain(|n| *n != package.name),
+            }
+            // the longest in flight is the one most likely holding the run up
+            status(progress, in_flight.first().map(String::as_str), self.width)
+        };
+        Self::draw(line);
+    }
+}
+
+impl Drop for Bar {
+    fn drop(&mut self) {
+        lock(&LINE).clear();
+        let mut out = io::stdout().lock();
+        let _ = out.write_all(CLEAR);
+        let _ = out.flush();
+    }
+}
+
+/// A status line if stdout is a terminal, or else log lines
+#[must_use]
+pub fn observer() -> Box<dyn Observer> {
+    if io::stdout().is_terminal() {
+        Box::new(Bar::new())
+    } else {
+        Box::new(Log)
+    }
+}
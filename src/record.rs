@@ -0,0 +1,105 @@
+// record.rs
+//
+// Recordings capture the raw output of every fetch command so a run can be replayed offline. They
+// live under <dir>/<package>/<channel>.json. A package directory exists for every package that
+// wasn't skipped, which lets a replay reproduce the skips of the recorded run.
+
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::package::{Package, PackageChannel};
+use crate::utils::cmd::CmdOutput;
+
+/// Environment variables identifying what a command fetched
+///
+/// Others, like `VAGRANT_ROOT`, vary between machines and aren't recorded.
+const KEY_ENV: [&str; 4] = ["name", "channel", "upstream", "shortform"];
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Recording {
+    pub package: String,
+    pub channel: String,
+    pub fetch: String,
+    pub env: BTreeMap<String, String>,
+    pub output: CmdOutput,
+}
+
+impl Recording {
+    #[must_use]
+    pub fn new(
+        package: &Package,
+        channel: &PackageChannel,
+        env: &HashMap<&str, &str>,
+        output: CmdOutput,
+    ) -> Self {
+        Self {
+            package: package.name.clone(),
+            channel: channel.name.clone(),
+            fetch: channel.fetch.clone(),
+            env: key_env(env),
+            output,
+        }
+    }
+
+    fn path(dir: &Path, package: &str, channel: &str) -> PathBuf {
+        dir.join(package).join(format!("{channel}.json"))
+    }
+
+    /// Write the recording under `dir`
+    ///
+    /// # Errors
+    ///
+    /// Fails if the recording can't be written.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = Self::path(dir, &self.package, &self.channel);
+        fs::create_dir_all(dir.join(&self.package))?;
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .wrap_err_with(|| format!("Failed to write recording {}", path.display()))
+    }
+
+    /// Read a channel's recording from `dir`
+    ///
+    /// # Errors
+    ///
+    /// Fails if the recording can't be read or parsed.
+    pub fn load(dir: &Path, package: &str, channel: &str) -> Result<Self> {
+        let path = Self::path(dir, package, channel);
+        let raw = fs::read_to_string(&path)
+            .wrap_err_with(|| format!("No recording at {}", path.display()))?;
+        serde_json::from_str(&raw)
+            .wrap_err_with(|| format!("Invalid recording at {}", path.display()))
+    }
+
+    /// Whether this recording was made for the same fetch command and environment
+    #[must_use]
+    pub fn matches(&self, channel: &PackageChannel, env: &HashMap<&str, &str>) -> bool {
+        self.fetch == channel.fetch && self.env == key_env(env)
+    }
+}
+
+fn key_env(env: &HashMap<&str, &str>) -> BTreeMap<String, String> {
+    env.iter()
+        .filter(|(k, _)| KEY_ENV.contains(k))
+        .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+        .collect()
+}
+
+/// Mark a package as fetched (rather than skipped) in a recording
+///
+/// # Errors
+///
+/// Fails if the marker can't be written.
+pub fn mark_package(dir: &Path, package: &Package) -> Result<()> {
+    fs::create_dir_all(dir.join(&package.name))
+        .wrap_err_with(|| format!("Failed to create recording for '{}'", package.name))
+}
+
+/// Whether a package was fetched (rather than skipped) in a recording
+#[must_use]
+pub fn has_package(dir: &Path, package: &Package) -> bool {
+    dir.join(&package.name).is_dir()
+}
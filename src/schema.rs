@@ -0,0 +1,49 @@
+// schema.rs
+//
+// JSON Schemas for the JSON API, generated from the types serialized into it
+
+use clap::ValueEnum;
+use schemars::schema_for;
+
+use crate::package::{AllJson, SCHEMA_VERSION, VersionsJson};
+
+#[derive(Debug, Default, Clone, Copy, ValueEnum)]
+pub enum SchemaKind {
+    /// `ALL.json`
+    #[default]
+    All,
+    /// `versions.json`
+    Versions,
+}
+
+impl SchemaKind {
+    pub const ALL: [Self; 2] = [Self::All, Self::Versions];
+
+    /// The file the schema is written to, alongside `ALL.json`
+    #[must_use]
+    pub const fn file_name(self) -> &'static str {
+        match self {
+            Self::All => "ALL.schema.json",
+            Self::Versions => "versions.schema.json",
+        }
+    }
+}
+
+/// Generate the JSON Schema for the current schema version
+#[must_use]
+pub fn generate(kind: SchemaKind) -> String {
+    let (mut schema, title) = match kind {
+        SchemaKind::All => (schema_for!(AllJson), "ALL.json"),
+        SchemaKind::Versions => (schema_for!(VersionsJson), "versions.json"),
+    };
+
+    schema.insert(
+        "title".into(),
+        format!("Vagrant {title}, schema version {SCHEMA_VERSION}").into(),
+    );
+
+    // serializing a schema can't fail
+    let mut json = serde_json::to_string_pretty(&schema).unwrap_or_default();
+    json.push('\n');
+    json
+}
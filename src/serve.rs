@@ -0,0 +1,360 @@
+// serve.rs
+//
+// A read-only HTTP API over the database. It mirrors the plaintext and JSON layouts of `p/`, in the
+// configured schema version, and adds a query endpoint. Responses are built from
+// `Package::read_versions` on every request, so they're always as fresh as the last write.
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tiny_http::{Header, Method, Request, Response};
+use tracing::{debug, error, info};
+
+use crate::context::Context;
+use crate::package::formats::{Format, txt};
+use crate::package::{Package, PackageVersions, bulk};
+
+pub struct Server<'a> {
+    ctx: &'a Context,
+    http: tiny_http::Server,
+}
+
+/// A response before it's sent
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+    /// The newest modification time of the files the body was built from
+    modified: Option<SystemTime>,
+}
+
+impl Reply {
+    const fn ok(content_type: &'static str, body: String, modified: Option<SystemTime>) -> Self {
+        Self {
+            status: 200,
+            content_type,
+            body,
+            modified,
+        }
+    }
+
+    fn json<T: serde::Serialize>(value: &T, modified: Option<SystemTime>) -> Result<Self> {
+        Ok(Self::rendered(
+            serde_json::to_string_pretty(value)?,
+            modified,
+        ))
+    }
+
+    /// JSON already rendered as it's written to `p/`
+    fn rendered(mut body: String, modified: Option<SystemTime>) -> Self {
+        body.push('\n');
+        Self::ok("application/json", body, modified)
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{message}\n"),
+            modified: None,
+        }
+    }
+
+    /// Tag the body by its length and the modification time of its files, both of which change
+    /// whenever it does, or not at all if it isn't built from files
+    fn etag(&self) -> Option<String> {
+        let modified = self.modified?.duration_since(UNIX_EPOCH).ok()?;
+        Some(format!(
+            "\"{:x}-{:x}\"",
+            self.body.len(),
+            modified.as_nanos()
+        ))
+    }
+}
+
+impl<'a> Server<'a> {
+    /// Listen on `addr`
+    ///
+    /// # Errors
+    ///
+    /// Fails if the address can't be bound.
+    pub fn bind(ctx: &'a Context, addr: &str) -> Result<Self> {
+        let http =
+            tiny_http::Server::http(addr).map_err(|e| eyre!("Failed to bind {addr}: {e}"))?;
+        Ok(Self { ctx, http })
+    }
+
+    #[must_use]
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.http.server_addr().to_ip()
+    }
+
+    /// Serve requests until stopped
+    pub fn run(&self) {
+        if let Some(addr) = self.addr() {
+            info!("Serving {} on http://{addr}", self.ctx.root.display());
+        }
+
+        for request in self.http.incoming_requests() {
+            self.respond(request);
+        }
+    }
+
+    /// Stop serving, returning from `run`
+    pub fn stop(&self) {
+        self.http.unblock();
+    }
+
+    fn respond(&self, request: Request) {
+        let method = request.method().clone();
+        let url = request.url().to_string();
+
+        let reply = match method {
+            Method::Get | Method::Head => self.route(&url).unwrap_or_else(|e| {
+                error!("Failed to serve {url}: {e}");
+                Reply::error(500, "Internal server error")
+            }),
+            _ => Reply::error(405, "Method not allowed"),
+        };
+        debug!("{method} {url} -> {}", reply.status);
+
+        let etag = reply.etag();
+        let last_modified = reply.modified.map(httpdate::fmt_http_date);
+        let not_modified =
+            reply.status == 200 && is_fresh(&request, etag.as_deref(), reply.modified);
+
+        let status = if not_modified { 304 } else { reply.status };
+        let body = if not_modified || method == Method::Head {
+            String::new()
+        } else {
+            reply.body
+        };
+
+        let mut response = Response::from_string(body)
+            .with_status_code(status)
+            .with_header(header("Content-Type", reply.content_type));
+        if reply.status == 200 {
+            if let Some(etag) = etag {
+                response.add_header(header("ETag", &etag));
+            }
+            if let Some(last_modified) = last_modified {
+                response.add_header(header("Last-Modified", &last_modified));
+            }
+        }
+
+        if let Err(e) = request.respond(response) {
+            error!("Failed to respond to {url}: {e}");
+        }
+    }
+
+    fn route(&self, url: &str) -> Result<Reply> {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let segments = path
+            .trim_matches('/')
+            .split('/')
+            .map(decode)
+            .collect::<Vec<_>>();
+        let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+
+        match segments.as_slice() {
+            ["ALL.json"] => {
+                let (all, modified) = self.all()?;
+                let body = Format::Json.render(self.ctx, &all)?;
+                Ok(Reply::rendered(String::from_utf8(body)?, modified))
+            }
+            ["ALL.txt"] => {
+                let (all, modified) = self.all()?;
+                Ok(Reply::ok("text/plain; charset=utf-8", txt(&all), modified))
+            }
+            ["query"] => self.query(query),
+            ["p", package, file] => self.package(package, |package, p| match *file {
+                "versions.json" => Ok(Reply::rendered(
+                    package.render_versions_json(self.ctx, &p.versions)?,
+                    None,
+                )),
+                "versions.txt" => Ok(Reply::ok(
+                    "text/plain; charset=utf-8",
+                    p.versions.iter().fold(String::new(), |acc, c| {
+                        format!("{acc}{}\t{}\n", c.channel, c.version)
+                    }),
+                    None,
+                )),
+                _ => Ok(Reply::error(404, "Not found")),
+            }),
+            ["p", package, "channels", channel] => self.package(package, |_, p| {
+                Ok(p.versions
+                    .iter()
+                    .find(|c| c.channel == *channel)
+                    .map_or_else(
+                        || Reply::error(404, "No such channel"),
+                        |c| Reply::ok("text/plain; charset=utf-8", c.version.clone(), None),
+                    ))
+            }),
+            _ => Ok(Reply::error(404, "Not found")),
+        }
+    }
+
+    /// Build a reply from a single package's versions
+    fn package<F>(&self, name: &str, f: F) -> Result<Reply>
+    where
+        F: FnOnce(&Package, &PackageVersions) -> Result<Reply>,
+    {
+        // names are directories under p/, so anything that could escape it doesn't exist
+        let dir = self.ctx.packages_dir().join(name);
+        let path = dir.join("versions.json");
+        if name.is_empty()
+            || name.starts_with('.')
+            || name.contains(['/', '\\'])
+            || !dir.join("config").is_file()
+        {
+            return Ok(Reply::error(404, "No such package"));
+        }
+
+        let package = Package::from_name(self.ctx, name)?;
+        let Some(versions) = package.read_previous_versions(self.ctx)? else {
+            return Ok(Reply::error(404, "No such package"));
+        };
+
+        let mut reply = f(&package, &PackageVersions::new(name, versions))?;
+        reply.modified = modified(&path);
+        Ok(reply)
+    }
+
+    /// Read every package's versions, along with the newest modification time among them
+    fn all(&self) -> Result<(Vec<PackageVersions>, Option<SystemTime>)> {
+        let mut all = vec![];
+        let mut newest = None;
+        for package in bulk::find_all(self.ctx)? {
+            if let Some(versions) = package.read_previous_versions(self.ctx)? {
+                let path = package.get_package_path(self.ctx).join("versions.json");
+                newest = newest.max(modified(&path));
+                all.push((package, versions));
+            }
+        }
+
+        let all = all
+            .into_iter()
+            .map(|(p, v)| PackageVersions::new(&p.name, v))
+            .collect();
+        Ok((all, newest))
+    }
+
+    /// Filter packages and channels by `channel`, `tag`, and `changed_since`
+    fn query(&self, query: &str) -> Result<Reply> {
+        let params = query
+            .split('&')
+            .filter_map(|kv| kv.split_once('='))
+            .map(|(k, v)| (decode(k), decode(v)))
+            .collect::<HashMap<_, _>>();
+
+        for key in params.keys() {
+            if !["channel", "tag", "changed_since"].contains(&key.as_str()) {
+                return Ok(Reply::error(400, &format!("Unknown parameter '{key}'")));
+            }
+        }
+
+        let since = match params.get("changed_since") {
+            Some(s) => match humantime::parse_rfc3339_weak(s) {
+                Ok(t) => Some(t),
+                Err(e) => return Ok(Reply::error(400, &format!("Invalid changed_since: {e}"))),
+            },
+            None => None,
+        };
+
+        let tagged = match params.get("tag") {
+            Some(tag) => Some(
+                bulk::find_all(self.ctx)?
+                    .into_iter()
+                    .filter(|p| p.config.tags.contains(tag))
+                    .map(|p| p.name)
+                    .collect::<Vec<_>>(),
+            ),
+            None => None,
+        };
+
+        let (all, modified) = self.all()?;
+        let matches = all
+            .into_iter()
+            .filter(|p| tagged.as_ref().is_none_or(|t| t.contains(&p.package)))
+            .map(|mut p| {
+                p.versions.retain(|c| {
+                    params.get("channel").is_none_or(|ch| c.channel == *ch)
+                        && since.is_none_or(|since| {
+                            c.changed_at
+                                .as_deref()
+                                .and_then(|t| humantime::parse_rfc3339_weak(t).ok())
+                                .is_some_and(|t| t >= since)
+                        })
+                });
+                p
+            })
+            .filter(|p| !p.versions.is_empty())
+            .collect::<Vec<_>>();
+
+        Reply::json(&matches, modified)
+    }
+}
+
+/// Whether the client's cached copy is still good
+fn is_fresh(request: &Request, etag: Option<&str>, modified: Option<SystemTime>) -> bool {
+    let get = |name: &'static str| {
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.as_str().to_string())
+    };
+
+    if let Some(tags) = get("If-None-Match") {
+        return tags
+            .split(',')
+            .any(|t| Some(t.trim()) == etag || t.trim() == "*");
+    }
+
+    // http dates only have second precision
+    let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    match (get("If-Modified-Since"), modified) {
+        (Some(since), Some(modified)) => {
+            httpdate::parse_http_date(&since).is_ok_and(|since| secs(modified) <= secs(since))
+        }
+        _ => false,
+    }
+}
+
+fn modified(path: &std::path::Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn header(name: &str, value: &str) -> Header {
+    // only ever called with valid header names and values
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap_or_else(|()| unreachable!())
+}
+
+/// Percent-decode a url component
+fn decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (escaped, bytes[i]) {
+            (Some(b), _) => {
+                decoded.push(b);
+                i += 3;
+                continue;
+            }
+            (None, b'+') => decoded.push(b' '),
+            (None, b) => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,313 @@
+// site.rs
+//
+// A static site rendered from the database: an index of every package and channel, a page per
+// package with its history, and Atom and RSS feeds of version changes, both overall and per
+// package. History comes from `ALL.sqlite` when it's written, and otherwise from each channel's
+// `changed_at`.
+
+use color_eyre::Result;
+use color_eyre::eyre::ensure;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::context::Context;
+use crate::package::PackageVersions;
+use crate::package::bulk;
+use crate::package::formats::{self, Change, Format};
+use crate::utils::fs::Transaction;
+use crate::utils::str::escape;
+
+/// The number of changes in each feed
+const FEED_LEN: usize = 50;
+
+const STYLE: &str = "
+body { font-family: sans-serif; max-width: 60rem; margin: 2rem auto; padding: 0 1rem; }
+table { border-collapse: collapse; width: 100%; }
+th, td { text-align: left; padding: 0.25rem 0.5rem; border-bottom: 1px solid #ccc; }
+code { word-break: break-all; }
+";
+
+struct Site<'a> {
+    base_url: &'a str,
+    packages: Vec<PackageVersions>,
+    /// Newest first
+    history: Vec<Change>,
+}
+
+/// Render the site into `out`, returning the number of pages written
+///
+/// `base_url` must be absolute, as feeds identify themselves and link to pages by it.
+///
+/// # Errors
+///
+/// Fails if `base_url` isn't absolute, or the site can't be written.
+pub fn build(ctx: &Context, out: &Path, base_url: &str) -> Result<usize> {
+    ensure!(
+        base_url.contains("://"),
+        "The base url '{base_url}' is not absolute"
+    );
+
+    let mut packages = vec![];
+    for package in bulk::find_all(ctx)? {
+        if let Some(versions) = package.read_previous_versions(ctx)? {
+            packages.push(PackageVersions::new(&package.name, versions));
+        }
+    }
+
+    let db = ctx.packages_dir().join(Format::Sqlite.file_name());
+    let mut history = if db.exists() {
+        formats::read_history(&db)?
+    } else {
+        packages
+            .iter()
+            .flat_map(|p| {
+                p.versions.iter().filter_map(|c| {
+                    Some(Change {
+                        package: p.package.clone(),
+                        channel: c.channel.clone(),
+                        version: c.version.clone(),
+                        seen_at: c.changed_at.clone()?,
+                    })
+                })
+            })
+            .collect()
+    };
+
+    // RFC 3339 timestamps in UTC sort lexicographically, and the sort is stable so ties keep the
+    // order they were recorded in
+    history.reverse();
+    history.sort_by(|a, b| b.seen_at.cmp(&a.seen_at));
+
+    let site = Site {
+        base_url: base_url.trim_end_matches('/'),
+        packages,
+        history,
+    };
+
+    let mut txn = Transaction::new();
+    txn.stage(out.join("index.html"), site.index())?;
+    txn.stage(out.join("atom.xml"), site.atom(None))?;
+    txn.stage(out.join("rss.xml"), site.rss(None))?;
+
+    for p in &site.packages {
+        let dir = out.join("p").join(&p.package);
+        txn.stage(dir.join("index.html"), site.package(p))?;
+        txn.stage(dir.join("atom.xml"), site.atom(Some(&p.package)))?;
+        txn.stage(dir.join("rss.xml"), site.rss(Some(&p.package)))?;
+    }
+
+    txn.commit()?;
+    Ok(site.packages.len() + 1)
+}
+
+impl Site<'_> {
+    /// The absolute url of a path in the site
+    fn url(&self, path: &str) -> String {
+        format!("{}/{path}", self.base_url)
+    }
+
+    fn changes<'a>(&'a self, package: Option<&'a str>) -> impl Iterator<Item = &'a Change> {
+        self.history
+            .iter()
+            .filter(move |c| package.is_none_or(|p| c.package == p))
+            .take(FEED_LEN)
+    }
+
+    fn index(&self) -> String {
+        let mut rows = String::new();
+        for p in &self.packages {
+            let name = escape(&p.package);
+            for c in &p.versions {
+                let _ = writeln!(
+                    rows,
+                    r#"<tr><td><a href="p/{name}/">{name}</a></td><td>{}</td><td><code>{}</code></td><td>{}</td><td>{}</td></tr>"#,
+                    escape(&c.channel),
+                    escape(&c.version),
+                    escape(c.changed_at.as_deref().unwrap_or_default()),
+                    c.status.map(|s| s.to_string()).unwrap_or_default(),
+                );
+            }
+        }
+
+        page(
+            "Vagrant",
+            "atom.xml",
+            "rss.xml",
+            &format!(
+                "<h1>Vagrant</h1>\n\
+                <p>{} packages. Feeds: <a href=\"atom.xml\">Atom</a>, <a href=\"rss.xml\">RSS</a></p>\n\
+                <table>\n<tr><th>Package</th><th>Channel</th><th>Version</th><th>Changed</th><th>Status</th></tr>\n{rows}</table>",
+                self.packages.len()
+            ),
+        )
+    }
+
+    fn package(&self, p: &PackageVersions) -> String {
+        let name = escape(&p.package);
+
+        let mut channels = String::new();
+        for c in &p.versions {
+            let _ = writeln!(
+                channels,
+                "<tr><td>{}</td><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(&c.channel),
+                escape(&c.version),
+                escape(c.changed_at.as_deref().unwrap_or_default()),
+                escape(c.fetched_at.as_deref().unwrap_or_default()),
+                c.status.map(|s| s.to_string()).unwrap_or_default(),
+            );
+        }
+
+        let mut history = String::new();
+        for c in self.history.iter().filter(|c| c.package == p.package) {
+            let _ = writeln!(
+                history,
+                "<tr><td>{}</td><td>{}</td><td><code>{}</code></td></tr>",
+                escape(&c.seen_at),
+                escape(&c.channel),
+                escape(&c.version),
+            );
+        }
+
+        page(
+            &p.package,
+            "atom.xml",
+            "rss.xml",
+            &format!(
+                "<p><a href=\"../../\">All packages</a></p>\n\
+                <h1>{name}</h1>\n\
+                <p>Feeds: <a href=\"atom.xml\">Atom</a>, <a href=\"rss.xml\">RSS</a></p>\n\
+                <table>\n<tr><th>Channel</th><th>Version</th><th>Changed</th><th>Fetched</th><th>Status</th></tr>\n{channels}</table>\n\
+                <h2>History</h2>\n\
+                <table>\n<tr><th>Seen</th><th>Channel</th><th>Version</th></tr>\n{history}</table>"
+            ),
+        )
+    }
+
+    fn atom(&self, package: Option<&str>) -> String {
+        let (title, path) = feed_meta(package);
+        let updated = self.changes(package).next().map_or_else(
+            || humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            |c| c.seen_at.clone(),
+        );
+
+        let mut entries = String::new();
+        for c in self.changes(package) {
+            let _ = write!(
+                entries,
+                "<entry>\n\
+                <title>{title}</title>\n\
+                <id>{id}</id>\n\
+                <updated>{updated}</updated>\n\
+                <link href=\"{link}\"/>\n\
+                <summary>{summary}</summary>\n\
+                </entry>\n",
+                title = escape(&format!("{}:{} {}", c.package, c.channel, c.version)),
+                id = escape(&urn(c)),
+                updated = escape(&c.seen_at),
+                link = escape(&self.url(&format!("p/{}/", c.package))),
+                summary = escape(&summary(c)),
+            );
+        }
+
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+            <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+            <title>{title}</title>\n\
+            <id>{id}</id>\n\
+            <updated>{updated}</updated>\n\
+            <author><name>Vagrant</name></author>\n\
+            <link rel=\"self\" href=\"{feed}\"/>\n\
+            <link href=\"{link}\"/>\n\
+            {entries}</feed>\n",
+            title = escape(&title),
+            id = escape(&self.url(&format!("{path}atom.xml"))),
+            updated = escape(&updated),
+            feed = escape(&self.url(&format!("{path}atom.xml"))),
+            link = escape(&self.url(&path)),
+        )
+    }
+
+    fn rss(&self, package: Option<&str>) -> String {
+        let (title, path) = feed_meta(package);
+
+        let mut items = String::new();
+        for c in self.changes(package) {
+            let pub_date = humantime::parse_rfc3339(&c.seen_at)
+                .map(|t| format!("<pubDate>{}</pubDate>\n", httpdate::fmt_http_date(t)))
+                .unwrap_or_default();
+
+            let _ = write!(
+                items,
+                "<item>\n\
+                <title>{title}</title>\n\
+                <link>{link}</link>\n\
+                <guid isPermaLink=\"false\">{guid}</guid>\n\
+                {pub_date}\
+                <description>{description}</description>\n\
+                </item>\n",
+                title = escape(&format!("{}:{} {}", c.package, c.channel, c.version)),
+                link = escape(&self.url(&format!("p/{}/", c.package))),
+                guid = escape(&urn(c)),
+                description = escape(&summary(c)),
+            );
+        }
+
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+            <rss version=\"2.0\">\n\
+            <channel>\n\
+            <title>{title}</title>\n\
+            <link>{link}</link>\n\
+            <description>{title}</description>\n\
+            {items}</channel>\n\
+            </rss>\n",
+            title = escape(&title),
+            link = escape(&self.url(&path)),
+        )
+    }
+}
+
+/// The title and site path of a feed
+fn feed_meta(package: Option<&str>) -> (String, String) {
+    package.map_or_else(
+        || ("Vagrant version changes".to_string(), String::new()),
+        |p| {
+            (
+                format!("Vagrant version changes for {p}"),
+                format!("p/{p}/"),
+            )
+        },
+    )
+}
+
+/// A stable identifier for a change
+fn urn(c: &Change) -> String {
+    format!("urn:vagrant:{}:{}:{}", c.package, c.channel, c.version)
+}
+
+fn summary(c: &Change) -> String {
+    format!(
+        "The {} channel of {} is now {}",
+        c.channel, c.package, c.version
+    )
+}
+
+fn page(title: &str, atom: &str, rss: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n\
+        <html lang=\"en\">\n\
+        <head>\n\
+        <meta charset=\"utf-8\">\n\
+        <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+        <title>{title}</title>\n\
+        <link rel=\"alternate\" type=\"application/atom+xml\" href=\"{atom}\">\n\
+        <link rel=\"alternate\" type=\"application/rss+xml\" href=\"{rss}\">\n\
+        <style>{STYLE}</style>\n\
+        </head>\n\
+        <body>\n{body}\n</body>\n\
+        </html>\n",
+        title = escape(title),
+    )
+}
@@ -1,10 +1,14 @@
 // utils/sh.rs
 
 use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::io::Write;
 use std::process::{Command, Stdio};
+use std::thread;
 
 use color_eyre::Result;
 use color_eyre::eyre::Context;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tracing::{trace, warn};
 
@@ -20,28 +24,101 @@ enum CmdError {
     EmptyStdout,
 }
 
-/// # Lowish level function to execute a command and return stdout
+/// Raw output of a command, before it's judged to have succeeded or failed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CmdOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub code: i32,
+}
+
+/// # Lowish level function to execute a command and capture its output
+///
+/// Use [`check`] to turn the output into a result.
+///
+/// # Errors
+///
+/// Fails if the command can't be spawned or waited on.
+pub fn run<S: BuildHasher>(
+    cmd: &[&str],
+    env: HashMap<&str, &str, S>,
+    cwd: &str,
+) -> Result<CmdOutput> {
+    spawn(cmd, env, cwd, None)
+}
+
+/// Like [`run`], but with `input` written to the command's stdin
+///
+/// # Errors
+///
+/// Fails as [`run`] does.
+pub fn pipe<S: BuildHasher>(
+    cmd: &[&str],
+    env: HashMap<&str, &str, S>,
+    cwd: &str,
+    input: &[u8],
+) -> Result<CmdOutput> {
+    spawn(cmd, env, cwd, Some(input))
+}
+
 #[allow(clippy::similar_names)]
-pub fn cmd(cmd: &[&str], env: HashMap<&str, &str>, cwd: &str) -> Result<String> {
+fn spawn<S: BuildHasher>(
+    cmd: &[&str],
+    env: HashMap<&str, &str, S>,
+    cwd: &str,
+    input: Option<&[u8]>,
+) -> Result<CmdOutput> {
     trace!("Evaluating command: {}", cmd.join(" "));
 
     let (arg0, args) = cmd.split_first().expect("command should not be empty");
-    let child = Command::new(arg0)
+    let mut child = Command::new(arg0)
         .args(args)
         .envs(env)
         .current_dir(cwd)
-        .stdin(Stdio::null())
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
         .wrap_err("Failed to spawn command")?;
 
-    let output = child
-        .wait_with_output()
-        .wrap_err("Failed to wait on child")?;
-    let code = output.status.code().unwrap_or(1);
-    let out = String::from_utf8_lossy(&output.stdout).to_string();
-    let err = String::from_utf8_lossy(&output.stderr).to_string();
+    // written from another thread so a command that writes before it reads can't deadlock
+    let output = thread::scope(|s| {
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            s.spawn(move || {
+                // a command is free to exit without reading its input
+                let _ = stdin.write_all(input);
+            });
+        }
+
+        child.wait_with_output()
+    })
+    .wrap_err("Failed to wait on child")?;
+
+    Ok(CmdOutput {
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        code: output.status.code().unwrap_or(1),
+    })
+}
+
+/// Return stdout if a command succeeded
+///
+/// A command is considered to have failed if it wrote to stderr, exited with a nonzero status, or
+/// wrote nothing to stdout.
+///
+/// # Errors
+///
+/// Fails if the command failed as described above.
+pub fn check(output: CmdOutput) -> Result<String> {
+    let CmdOutput {
+        stdout: out,
+        stderr: err,
+        code,
+    } = output;
 
     trace!("STDOUT: {out}");
 
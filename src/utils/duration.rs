@@ -0,0 +1,55 @@
+// utils/duration.rs
+
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::Duration;
+
+/// Deserialize a human-readable duration such as "30m" or "7d"
+///
+/// Intended for use with `#[serde(deserialize_with = "...")]`.
+///
+/// # Errors
+///
+/// Fails if the value isn't a string or a valid duration.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    humantime::parse_duration(&s).map_err(serde::de::Error::custom)
+}
+
+/// Deserialize a map of human-readable durations
+///
+/// # Errors
+///
+/// Fails if any value isn't a valid duration.
+pub fn deserialize_map<'de, D, K>(deserializer: D) -> Result<HashMap<K, Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de> + Eq + Hash,
+{
+    HashMap::<K, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(k, v)| {
+            humantime::parse_duration(&v)
+                .map(|d| (k, d))
+                .map_err(serde::de::Error::custom)
+        })
+        .collect()
+}
+
+/// Deserialize an optional human-readable duration
+///
+/// Intended for use with `#[serde(default, deserialize_with = "...")]`.
+///
+/// # Errors
+///
+/// Fails if the value is present but isn't a valid duration.
+pub fn deserialize_option<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize(deserializer).map(Some)
+}
@@ -2,6 +2,7 @@ use std::f64;
 
 // This exists so I can impl Hash for a struct with an f64 field
 #[allow(clippy::unreadable_literal)]
+#[must_use]
 pub const fn defloat(val: f64) -> (u64, i16, i8) {
     let bits: u64 = f64::to_bits(val);
     let sign: i8 = if bits >> 63 == 0 { 1 } else { -1 };
@@ -0,0 +1,95 @@
+// utils/fs.rs
+
+use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// A set of file writes and removals applied all at once
+///
+/// Contents are staged to hidden temporary files alongside their destinations, then renamed into
+/// place in the order they were staged. Nothing is touched until every write has been staged, and
+/// each rename is atomic, so readers never see a partially written file. Removals happen after
+/// every write. Staged files are removed if the transaction is dropped without being committed.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    staged: Vec<(PathBuf, PathBuf)>,
+    removals: Vec<PathBuf>,
+}
+
+impl Transaction {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stage a write of `contents` to `path`
+    ///
+    /// # Errors
+    ///
+    /// Fails if the staging file can't be written.
+    pub fn stage<P: AsRef<Path>, C: AsRef<[u8]>>(&mut self, path: P, contents: C) -> Result<()> {
+        let path = path.as_ref();
+        let tmp = tmp_path(path);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .wrap_err_with(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        fs::write(&tmp, contents)
+            .wrap_err_with(|| format!("Failed to stage {}", path.display()))?;
+        self.staged.push((tmp, path.to_path_buf()));
+        Ok(())
+    }
+
+    /// Stage the removal of `path`
+    pub fn remove<P: AsRef<Path>>(&mut self, path: P) {
+        self.removals.push(path.as_ref().to_path_buf());
+    }
+
+    /// Rename every staged file into place, then remove files staged for removal
+    ///
+    /// # Errors
+    ///
+    /// Fails if a staged file can't be renamed or removed.
+    pub fn commit(mut self) -> Result<()> {
+        self.staged.reverse();
+        while let Some((tmp, path)) = self.staged.pop() {
+            if let Err(e) = fs::rename(&tmp, &path) {
+                let _ = fs::remove_file(&tmp);
+                return Err(e).wrap_err_with(|| format!("Failed to write {}", path.display()));
+            }
+        }
+
+        for path in &self.removals {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    return Err(e).wrap_err_with(|| format!("Failed to remove {}", path.display()));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        for (tmp, _) in &self.staged {
+            if let Err(e) = fs::remove_file(tmp) {
+                warn!("Failed to remove staged file {}: {e}", tmp.display());
+            }
+        }
+    }
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.tmp"))
+}
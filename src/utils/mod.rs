@@ -1,5 +1,7 @@
 pub mod cmd;
+pub mod duration;
 pub mod float;
+pub mod fs;
 pub mod shortform;
 pub mod str;
 pub mod ver;
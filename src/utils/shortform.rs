@@ -1,5 +1,6 @@
 // utils/shortform.rs
 
+#[must_use]
 pub fn get_shortform(maybe_short: &str) -> String {
     if !maybe_short.contains("github.com") {
         return maybe_short.to_string();
@@ -14,10 +15,12 @@ pub fn get_shortform(maybe_short: &str) -> String {
     }
 }
 
+#[must_use]
 pub fn is_shortform(maybe_short: &str) -> bool {
     maybe_short.split('/').count() == 2
 }
 
+#[must_use]
 pub fn get_longform(maybe_short: &str) -> String {
     if is_shortform(maybe_short) {
         format!("https://github.com/{maybe_short}.git")
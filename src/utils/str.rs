@@ -1,6 +1,57 @@
 /// Returns the basename of a string, ie everything after the final slash, or just the string if
 /// there are no slashes
 #[inline]
+#[must_use]
 pub fn basename(s: &str) -> &str {
     s.rsplit_once('/').map_or(s, |s| s.1)
 }
+
+/// Escapes a string for use in HTML or XML text and attributes
+#[must_use]
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Returns the host of a url, including scp-like git urls such as `git@host:path`
+#[must_use]
+pub fn host(url: &str) -> Option<&str> {
+    let rest = url.split_once("://").map_or_else(
+        || url.split_once(':').map(|(authority, _)| authority),
+        |(_, rest)| Some(rest),
+    )?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+    let host = host.split(':').next()?;
+    (!host.is_empty()).then_some(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn host_handles_urls_and_git_remotes() {
+        assert_eq!(
+            host("https://gitlab.freedesktop.org/mesa/mesa.git"),
+            Some("gitlab.freedesktop.org")
+        );
+        assert_eq!(
+            host("https://user@mirror.rit.edu:8443/gnu?x"),
+            Some("mirror.rit.edu")
+        );
+        assert_eq!(host("git@github.com:tox-wtf/vagrant"), Some("github.com"));
+        assert_eq!(host("not a url"), None);
+    }
+}
@@ -1,5 +1,7 @@
 // utils/ver.rs
 
+use std::cmp::Ordering;
+
 use crate::package::Package;
 use crate::utils::str::basename;
 
@@ -10,6 +12,7 @@ pub struct Version {
 }
 
 impl Version {
+    #[must_use]
     pub const fn new(raw: String) -> Self {
         Self {
             raw,
@@ -17,13 +20,14 @@ impl Version {
         }
     }
 
+    /// Format the last non-blank line of the raw output, which is left empty if there is none
     pub fn trim(&mut self, package: &Package) {
         let ver = self
             .raw
             .lines()
-            .filter(|l| !l.is_empty())
-            .next_back()
-            .map_or_else(|| unreachable!("No output"), str::to_lowercase);
+            .rfind(|l| !l.trim().is_empty())
+            .map(str::to_lowercase)
+            .unwrap_or_default();
 
         let name = basename(&package.name);
 
@@ -35,3 +39,92 @@ impl Version {
         self.fmt = ver.trim().to_string();
     }
 }
+
+#[derive(Debug, PartialEq, Eq)]
+enum Segment<'a> {
+    Num(&'a str),
+    Alpha(&'a str),
+}
+
+fn segments(ver: &str) -> Vec<Segment<'_>> {
+    let mut segments = vec![];
+    let mut rest = ver;
+
+    while let Some(c) = rest.chars().next() {
+        let is_digit = c.is_ascii_digit();
+        let is_alpha = c.is_alphabetic();
+        if !is_digit && !is_alpha {
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        let end = rest
+            .find(|c: char| {
+                if is_digit {
+                    !c.is_ascii_digit()
+                } else {
+                    !c.is_alphabetic()
+                }
+            })
+            .unwrap_or(rest.len());
+
+        let (seg, tail) = rest.split_at(end);
+        segments.push(if is_digit {
+            Segment::Num(seg.trim_start_matches('0'))
+        } else {
+            Segment::Alpha(seg)
+        });
+        rest = tail;
+    }
+
+    segments
+}
+
+/// Compare two version strings
+///
+/// Versions are split into numeric and alphabetic segments. Numeric segments compare numerically,
+/// and alphabetic segments are treated as prerelease labels, so `1.0-rc1` < `1.0` < `1.0.1`.
+#[must_use]
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let (a, b) = (segments(a), segments(b));
+
+    for i in 0..a.len().max(b.len()) {
+        let ord = match (a.get(i), b.get(i)) {
+            (Some(Segment::Num(x)), Some(Segment::Num(y))) => x.len().cmp(&y.len()).then(x.cmp(y)),
+            (Some(Segment::Alpha(x)), Some(Segment::Alpha(y))) => x.cmp(y),
+            (Some(Segment::Num(_)), Some(Segment::Alpha(_)) | None)
+            | (None, Some(Segment::Alpha(_))) => Ordering::Greater,
+            (Some(Segment::Alpha(_)), Some(Segment::Num(_)) | None)
+            | (None, Some(Segment::Num(_))) => Ordering::Less,
+            (None, None) => Ordering::Equal,
+        };
+
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+
+    Ordering::Equal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn compare_orders_versions() {
+        let mut versions = vec!["1.10", "1.2", "1.0-rc1", "1.0", "1.0.1", "0.9", "1.0-beta2"];
+        versions.sort_by(|a, b| compare(a, b));
+        assert_eq!(
+            versions,
+            ["0.9", "1.0-beta2", "1.0-rc1", "1.0", "1.0.1", "1.2", "1.10"]
+        );
+    }
+
+    #[test]
+    fn compare_ignores_leading_zeros() {
+        assert_eq!(compare("2024.01", "2024.1"), Ordering::Equal);
+        assert_eq!(compare("20240102", "20231231"), Ordering::Greater);
+    }
+}
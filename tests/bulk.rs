@@ -0,0 +1,627 @@
+mod common;
+
+use common::{COMMIT, Fixture, vc};
+use indexmap::IndexMap;
+use pretty_assertions::assert_eq;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, SystemTime};
+use vagrant::args::Args;
+use vagrant::package::engine::{Engine, Quiet};
+use vagrant::package::fetcher::{Dispatcher, MockFetcher};
+use vagrant::package::{Package, Status, VersionChannel, bulk};
+
+fn pairs(versions: &[VersionChannel]) -> Vec<(&str, &str)> {
+    versions
+        .iter()
+        .map(|v| (v.channel.as_str(), v.version.as_str()))
+        .collect()
+}
+
+#[test]
+fn find_all_finds_configured_packages() {
+    let f = Fixture::new();
+    f.package("zlib", &[("release", "stubrelease")]);
+    f.package("acl", &[("release", "stubrelease")]);
+    f.write("p/not-a-package/versions.txt", "");
+
+    let packages = bulk::find_all(&f.ctx).expect("find_all should succeed");
+    let names = packages.iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
+
+    assert_eq!(names, ["acl", "zlib"]);
+}
+
+#[test]
+fn fetch_all_fetches_through_shell_library() {
+    let f = Fixture::new();
+    let pkg = f.package(
+        "foo",
+        &[
+            ("release", "stubrelease"),
+            ("unstable", "stubunstable"),
+            ("commit", "stubcommit"),
+        ],
+    );
+
+    let map = bulk::fetch_all(&f.ctx, std::slice::from_ref(&pkg), &Dispatcher)
+        .expect("fetch should succeed");
+
+    assert_eq!(
+        pairs(&map[&pkg]),
+        [
+            ("release", "1.2.3"),
+            ("unstable", "1.3.0-rc1"),
+            ("commit", COMMIT)
+        ]
+    );
+    assert_eq!(f.read(".vagrant-cache/total"), "1");
+    assert_eq!(f.read(".vagrant-cache/checked"), "1");
+    assert_eq!(f.read(".vagrant-cache/failed"), "0");
+}
+
+#[test]
+fn fetch_all_falls_back_for_failed_packages() {
+    let f = Fixture::new();
+    let good = f.package("good", &[("release", "stubrelease")]);
+    let bad = f.package("bad", &[("release", "stubfail")]);
+    let garbage = f.package("garbage", &[("release", "stubgarbage")]);
+    f.versions("bad", &[("release", "0.9")]);
+    f.versions("garbage", &[("release", "0.8")]);
+
+    let map = bulk::fetch_all(
+        &f.ctx,
+        &[good.clone(), bad.clone(), garbage.clone()],
+        &Dispatcher,
+    )
+    .expect("fetch should succeed");
+
+    assert_eq!(pairs(&map[&good]), [("release", "1.2.3")]);
+    assert_eq!(pairs(&map[&bad]), [("release", "0.9")]);
+    assert_eq!(pairs(&map[&garbage]), [("release", "0.8")]);
+    assert_eq!(f.read(".vagrant-cache/failed"), "2");
+    assert_eq!(f.read(".vagrant-cache/failed_packages"), "bad\ngarbage\n");
+    assert_eq!(f.read(".vagrant-cache/checked"), "1");
+}
+
+#[test]
+fn fetch_all_leaves_out_failed_new_packages() {
+    let f = Fixture::new();
+    let good = f.package("good", &[("release", "stubrelease")]);
+    let new = f.package("new", &[("release", "stubfail")]);
+
+    let map = bulk::fetch_all(&f.ctx, &[good.clone(), new.clone()], &Dispatcher)
+        .expect("a failed new package should not abort the run");
+
+    assert_eq!(pairs(&map[&good]), [("release", "1.2.3")]);
+    assert!(!map.contains_key(&new));
+    assert_eq!(f.read(".vagrant-cache/total"), "2");
+    assert_eq!(f.read(".vagrant-cache/failed"), "1");
+    assert_eq!(f.read(".vagrant-cache/checked"), "1");
+
+    bulk::write_all(&f.ctx, &map).expect("write should succeed");
+    assert!(!f.path("p/new/versions.json").exists());
+    assert_eq!(f.read("p/ALL.txt"), "good\trelease\t1.2.3\n");
+}
+
+#[test]
+fn fetch_all_falls_back_per_channel() {
+    let f = Fixture::new();
+    let pkg = f.package(
+        "foo",
+        &[
+            ("release", "stubrelease"),
+            ("unstable", "stubfail"),
+            ("commit", "stubgarbage"),
+        ],
+    );
+    f.versions("foo", &[("release", "1.0"), ("unstable", "1.1-rc1")]);
+
+    let map = bulk::fetch_all(&f.ctx, std::slice::from_ref(&pkg), &Dispatcher)
+        .expect("fetch should succeed");
+
+    // commit has no previous value to fall back on
+    assert_eq!(
+        pairs(&map[&pkg]),
+        [("release", "1.2.3"), ("unstable", "1.1-rc1")]
+    );
+    assert_eq!(f.read(".vagrant-cache/failed"), "1");
+    assert_eq!(
+        f.read(".vagrant-cache/failed_channels"),
+        "foo:unstable\nfoo:commit\n"
+    );
+}
+
+#[test]
+fn fetch_all_fails_channels_with_empty_versions() {
+    let f = Fixture::new();
+    let pkg = f.package(
+        "foo",
+        &[
+            ("release", "stubrelease"),
+            ("unstable", "stubunstable"),
+            ("commit", "stubcommit"),
+        ],
+    );
+    f.versions("foo", &[("release", "1.0"), ("unstable", "1.1-rc1")]);
+    let fetcher = MockFetcher::default()
+        .with("foo", "release", "")
+        .with("foo", "unstable", " \n\n")
+        .with("foo", "commit", COMMIT);
+
+    let map = bulk::fetch_all(&f.ctx, std::slice::from_ref(&pkg), &fetcher)
+        .expect("fetch should succeed");
+
+    assert_eq!(
+        pairs(&map[&pkg]),
+        [
+            ("release", "1.0"),
+            ("unstable", "1.1-rc1"),
+            ("commit", COMMIT)
+        ]
+    );
+    assert_eq!(
+        f.read(".vagrant-cache/failed_channels"),
+        "foo:release\nfoo:unstable\n"
+    );
+}
+
+#[test]
+fn fetch_all_falls_back_for_skipped_packages() {
+    let f = Fixture::new();
+    let pkg = f.package_with("rare", "chance = 0.0", &[("release", "stubrelease")]);
+    f.versions("rare", &[("release", "0.1")]);
+
+    let map = bulk::fetch_all(&f.ctx, std::slice::from_ref(&pkg), &Dispatcher)
+        .expect("fetch should succeed");
+
+    assert_eq!(pairs(&map[&pkg]), [("release", "0.1")]);
+    assert_eq!(f.read(".vagrant-cache/skipped"), "1");
+}
+
+#[test]
+fn fetch_with_falls_back_for_cancelled_packages() {
+    let f = Fixture::new();
+    let old = f.package("old", &[("release", "stubrelease")]);
+    let new = f.package("new", &[("release", "stubrelease")]);
+    f.versions("old", &[("release", "0.1")]);
+
+    let cancel = AtomicBool::new(true);
+    let engine = Engine::new(4, &Quiet, &cancel);
+    let map = bulk::fetch_with(&f.ctx, &[old.clone(), new.clone()], &Dispatcher, &engine)
+        .expect("fetch should succeed");
+
+    assert_eq!(pairs(&map[&old]), [("release", "0.1")]);
+    assert_eq!(map[&old][0].status, Some(Status::Skipped));
+    assert!(!map.contains_key(&new));
+    assert_eq!(f.read(".vagrant-cache/skipped"), "2");
+    assert_eq!(f.read(".vagrant-cache/timings"), "");
+}
+
+#[test]
+fn fetch_all_writes_timings() {
+    let f = Fixture::new();
+    let packages = [
+        f.package("a", &[("release", "stubrelease")]),
+        f.package("b", &[("release", "stubrelease")]),
+    ];
+
+    bulk::fetch_all(&f.ctx, &packages, &Dispatcher).expect("fetch should succeed");
+
+    let timings = f.read(".vagrant-cache/timings");
+    let mut names = timings
+        .lines()
+        .map(|l| {
+            let (name, secs) = l.split_once('\t').expect("timing should have a name");
+            secs.parse::<f64>().expect("timing should be in seconds");
+            name
+        })
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    assert_eq!(names, ["a", "b"]);
+}
+
+#[test]
+fn fetch_all_carries_frozen_channels_forward() {
+    let f = Fixture::new();
+    let pkg = f.package_raw(
+        "foo",
+        r#"
+upstream = "stub/foo"
+
+[[channels]]
+name = "release"
+fetch = "stubrelease"
+
+[[channels]]
+name = "unstable"
+frozen = true
+fetch = "stubfail"
+"#,
+    );
+    f.versions("foo", &[("release", "1.0"), ("unstable", "1.1-rc1")]);
+
+    let map = bulk::fetch_all(&f.ctx, std::slice::from_ref(&pkg), &Dispatcher)
+        .expect("fetch should succeed");
+    assert_eq!(
+        pairs(&map[&pkg]),
+        [("release", "1.2.3"), ("unstable", "1.1-rc1")]
+    );
+    assert_eq!(f.read(".vagrant-cache/failed"), "0");
+
+    bulk::write_all(&f.ctx, &map).expect("write should succeed");
+
+    let versions: serde_json::Value =
+        serde_json::from_str(&f.read("p/foo/versions.json")).expect("versions.json should parse");
+    assert_eq!(versions[0].get("frozen"), None);
+    assert_eq!(versions[1]["frozen"], true);
+    assert_eq!(f.read("p/foo/channels/unstable"), "1.1-rc1");
+}
+
+#[test]
+fn write_all_writes_every_format() {
+    let f = Fixture::new();
+    let foo = f.package(
+        "foo",
+        &[("release", "stubrelease"), ("commit", "stubcommit")],
+    );
+    let bar = f.package("bar", &[("release", "stubrelease")]);
+
+    let mut map = IndexMap::<Package, Vec<VersionChannel>>::new();
+    map.insert(bar, vec![vc("release", "2.0")]);
+    map.insert(foo, vec![vc("release", "1.2.3"), vc("commit", COMMIT)]);
+
+    bulk::write_all(&f.ctx, &map).expect("write should succeed");
+
+    assert_eq!(f.read("p/foo/channels/release"), "1.2.3");
+    assert_eq!(f.read("p/foo/channels/commit"), COMMIT);
+    assert_eq!(
+        f.read("p/foo/versions.txt"),
+        format!("release\t1.2.3\ncommit\t{COMMIT}\n")
+    );
+    assert_eq!(
+        f.read("p/ALL.txt"),
+        format!("bar\trelease\t2.0\nfoo\trelease\t1.2.3\nfoo\tcommit\t{COMMIT}\n")
+    );
+
+    let versions: serde_json::Value =
+        serde_json::from_str(&f.read("p/foo/versions.json")).expect("versions.json should parse");
+    assert_eq!(versions[0]["channel"], "release");
+    assert_eq!(versions[0]["version"], "1.2.3");
+
+    let all: serde_json::Value =
+        serde_json::from_str(&f.read("p/ALL.json")).expect("ALL.json should parse");
+    assert_eq!(all[0]["package"], "bar");
+    assert_eq!(all[1]["versions"][1]["version"], COMMIT);
+}
+
+#[test]
+fn write_all_is_all_or_nothing() {
+    let f = Fixture::new();
+    let foo = f.package("foo", &[("release", "stubrelease")]);
+    let bar = f.package("bar", &[("release", "stubrelease")]);
+    f.write("p/ALL.txt", "old\n");
+
+    // a file where the channels directory belongs makes staging fail
+    f.write("p/foo/channels", "");
+
+    let mut map = IndexMap::<Package, Vec<VersionChannel>>::new();
+    for pkg in [bar, foo] {
+        map.insert(pkg, vec![vc("release", "2.0")]);
+    }
+
+    assert!(bulk::write_all(&f.ctx, &map).is_err());
+    assert!(!f.path("p/bar/versions.json").exists());
+    assert_eq!(f.read("p/ALL.txt"), "old\n");
+
+    let leftovers = walk(&f.path("p"))
+        .into_iter()
+        .filter(|p| p.to_string_lossy().ends_with(".tmp"))
+        .collect::<Vec<_>>();
+    assert_eq!(leftovers, Vec::<std::path::PathBuf>::new());
+}
+
+fn stale_map(f: &Fixture) -> IndexMap<Package, Vec<VersionChannel>> {
+    let pkg = f.package_raw(
+        "foo",
+        r#"
+upstream = "stub/foo"
+
+[[channels]]
+name = "release"
+fetch = "stubrelease"
+
+[[channels]]
+name = "commit"
+enabled = false
+fetch = "stubcommit"
+"#,
+    );
+    f.write("p/foo/channels/commit", COMMIT);
+    f.write("p/foo/channels/removed", "0.1");
+
+    let mut map = IndexMap::new();
+    map.insert(
+        pkg,
+        ["release", "commit", "removed"]
+            .into_iter()
+            .map(|c| vc(c, "1.0"))
+            .collect(),
+    );
+    map
+}
+
+#[test]
+fn write_all_prunes_stale_channels() {
+    let f = Fixture::new();
+    let map = stale_map(&f);
+
+    bulk::write_all(&f.ctx, &map).expect("write should succeed");
+
+    assert_eq!(f.read("p/foo/channels/release"), "1.0");
+    assert!(!f.path("p/foo/channels/commit").exists());
+    assert!(!f.path("p/foo/channels/removed").exists());
+    assert_eq!(f.read("p/foo/versions.txt"), "release\t1.0\n");
+    assert_eq!(f.read("p/ALL.txt"), "foo\trelease\t1.0\n");
+    assert_eq!(f.read(".vagrant-cache/pruned"), "foo:commit\nfoo:removed\n");
+}
+
+#[test]
+fn write_all_keeps_stale_channels_when_asked() {
+    let f = Fixture::with_args(Args {
+        keep_stale: true,
+        ..Default::default()
+    });
+    let map = stale_map(&f);
+
+    bulk::write_all(&f.ctx, &map).expect("write should succeed");
+
+    assert_eq!(f.read("p/foo/channels/commit"), "1.0");
+    assert_eq!(f.read("p/foo/channels/removed"), "1.0");
+    assert_eq!(f.read(".vagrant-cache/pruned"), "");
+}
+
+#[test]
+fn write_all_writes_channel_metadata() {
+    let f = Fixture::new();
+    let pkg = f.package(
+        "foo",
+        &[("release", "stubrelease"), ("unstable", "stubfail")],
+    );
+    f.write(
+        "p/foo/versions.json",
+        r#"[
+            { "channel": "release", "version": "1.2.3", "changed_at": "2020-01-01T00:00:00Z" },
+            { "channel": "unstable", "version": "1.3.0-rc1" }
+        ]"#,
+    );
+
+    let map = bulk::fetch_all(&f.ctx, std::slice::from_ref(&pkg), &Dispatcher)
+        .expect("fetch should succeed");
+    bulk::write_all(&f.ctx, &map).expect("write should succeed");
+
+    let versions: serde_json::Value =
+        serde_json::from_str(&f.read("p/foo/versions.json")).expect("versions.json should parse");
+    let release = &versions[0];
+    assert_eq!(release["status"], "fresh");
+    assert_eq!(release["source"], "https://github.com/stub/foo.git");
+    assert_eq!(release["raw"], "v1.2.3");
+    assert_eq!(release["changed_at"], "2020-01-01T00:00:00Z");
+    assert!(release["fetched_at"].is_string());
+
+    let unstable = &versions[1];
+    assert_eq!(unstable["status"], "fallback");
+    assert_eq!(unstable.get("fetched_at"), None);
+}
+
+#[test]
+fn write_all_honors_older_schema_versions() {
+    let mut f = Fixture::new();
+    f.write("vagrant.toml", "[output]\nschema = 1\n");
+    f.reload();
+    let pkg = f.package("foo", &[("release", "stubrelease")]);
+
+    let map = bulk::fetch_all(&f.ctx, std::slice::from_ref(&pkg), &Dispatcher)
+        .expect("fetch should succeed");
+    bulk::write_all(&f.ctx, &map).expect("write should succeed");
+
+    let versions: serde_json::Value =
+        serde_json::from_str(&f.read("p/foo/versions.json")).expect("versions.json should parse");
+    assert_eq!(
+        versions,
+        serde_json::json!([{ "channel": "release", "version": "1.2.3" }])
+    );
+}
+
+fn walk(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(dir)
+        .expect("dir should be readable")
+        .flatten()
+    {
+        let path = entry.path();
+        if path.is_dir() {
+            files.extend(walk(&path));
+        } else {
+            files.push(path);
+        }
+    }
+    files
+}
+
+#[test]
+fn replay_reproduces_recorded_run() {
+    let record = tempfile::tempdir().expect("tempdir should be created");
+
+    let f = Fixture::with_args(Args {
+        record: Some(record.path().to_path_buf()),
+        ..Default::default()
+    });
+    let pkg = f.package(
+        "foo",
+        &[("release", "stubrelease"), ("commit", "stubcommit")],
+    );
+    let recorded = bulk::fetch_all(&f.ctx, &[pkg], &Dispatcher).expect("fetch should succeed");
+
+    // the stub library is gone, so replayed output must come from the recording
+    let f = Fixture::with_args(Args {
+        replay: Some(record.path().to_path_buf()),
+        ..Default::default()
+    });
+    f.write("sh/lib.env", "");
+    let pkg = f.package(
+        "foo",
+        &[("release", "stubrelease"), ("commit", "stubcommit")],
+    );
+    let replayed = bulk::fetch_all(&f.ctx, &[pkg], &Dispatcher).expect("replay should succeed");
+
+    assert_eq!(
+        pairs(&recorded[0]),
+        [("release", "1.2.3"), ("commit", COMMIT)]
+    );
+    assert_eq!(pairs(&recorded[0]), pairs(&replayed[0]));
+}
+
+fn days_ago(days: u64) -> String {
+    let t = SystemTime::now() - Duration::from_hours(24 * days);
+    humantime::format_rfc3339_seconds(t).to_string()
+}
+
+#[test]
+fn fetch_all_checks_packages_by_interval() {
+    let f = Fixture::new();
+    let interval = "interval = \"7d\"";
+    let recent = f.package_with("recent", interval, &[("release", "stubrelease")]);
+    let stale = f.package_with("stale", interval, &[("release", "stubrelease")]);
+    let unknown = f.package_with("unknown", interval, &[("release", "stubrelease")]);
+    for name in ["recent", "stale", "unknown"] {
+        f.versions(name, &[("release", "1.0")]);
+    }
+    f.write("p/recent/last_checked", &days_ago(1));
+    f.write("p/stale/last_checked", &days_ago(8));
+
+    let map = bulk::fetch_all(
+        &f.ctx,
+        &[recent.clone(), stale.clone(), unknown.clone()],
+        &Dispatcher,
+    )
+    .expect("fetch should succeed");
+
+    assert_eq!(map[&recent][0].status, Some(Status::Skipped));
+    assert_eq!(map[&stale][0].status, Some(Status::Fresh));
+    assert_eq!(map[&unknown][0].status, Some(Status::Fresh));
+    assert_eq!(f.read(".vagrant-cache/skipped"), "1");
+}
+
+#[test]
+fn write_all_records_last_checked_for_clean_checks() {
+    let f = Fixture::new();
+    let clean = f.package("clean", &[("release", "stubrelease")]);
+    let partial = f.package(
+        "partial",
+        &[("release", "stubrelease"), ("unstable", "stubfail")],
+    );
+    let skipped = f.package("skipped", &[("release", "stubrelease")]);
+    f.versions("partial", &[("release", "1.0"), ("unstable", "1.1-rc1")]);
+
+    let with = |version: &str, status| VersionChannel {
+        status: Some(status),
+        ..vc("release", version)
+    };
+    let mut map = IndexMap::new();
+    map.insert(clean, vec![with("1.2.3", Status::Fresh)]);
+    map.insert(
+        partial,
+        vec![
+            with("1.2.3", Status::Fresh),
+            VersionChannel {
+                status: Some(Status::Fallback),
+                ..vc("unstable", "1.1-rc1")
+            },
+        ],
+    );
+    map.insert(skipped, vec![with("1.0", Status::Skipped)]);
+    bulk::write_all(&f.ctx, &map).expect("write should succeed");
+
+    let checked = humantime::parse_rfc3339(f.read("p/clean/last_checked").trim())
+        .expect("last_checked should be a timestamp");
+    assert!(checked <= SystemTime::now());
+    assert!(!f.path("p/partial/last_checked").exists());
+    assert!(!f.path("p/skipped/last_checked").exists());
+}
+
+#[test]
+fn write_all_refuses_regressions_of_monotonic_channels() {
+    let f = Fixture::new();
+    let pkg = f.package_raw(
+        "pkg",
+        "upstream = \"stub/pkg\"\n\n\
+        [[channels]]\nname = \"release\"\nfetch = \"stubrelease\"\n\n\
+        [[channels]]\nname = \"unstable\"\nfetch = \"stubunstable\"\nmonotonic = false\n\n\
+        [[channels]]\nname = \"lts\"\nfetch = \"stubrelease\"\nexpected = \".*\"\nmonotonic = true\n\n\
+        [[channels]]\nname = \"commit\"\nfetch = \"stubcommit\"\n",
+    );
+    let old_commit = "a".repeat(40);
+    f.versions(
+        "pkg",
+        &[
+            ("release", "25.01"),
+            ("unstable", "2.0-rc1"),
+            ("lts", "3.1"),
+            ("commit", &old_commit),
+        ],
+    );
+
+    let fresh = |channel: &str, version: &str| VersionChannel {
+        status: Some(Status::Fresh),
+        ..vc(channel, version)
+    };
+    let mut map = IndexMap::new();
+    map.insert(
+        pkg.clone(),
+        vec![
+            fresh("release", "24.09"),
+            fresh("unstable", "1.9-rc1"),
+            fresh("lts", "garbage"),
+            fresh("commit", COMMIT),
+        ],
+    );
+    let changes = bulk::write_all(&f.ctx, &map).expect("write should succeed");
+
+    let written = pkg
+        .read_previous_versions(&f.ctx)
+        .expect("versions should be readable")
+        .expect("versions should exist");
+    assert_eq!(
+        pairs(&written),
+        [
+            ("release", "25.01"),
+            ("unstable", "1.9-rc1"),
+            ("lts", "3.1"),
+            ("commit", COMMIT),
+        ]
+    );
+    assert_eq!(written[0].status, Some(Status::Fallback));
+    assert_eq!(
+        changes
+            .iter()
+            .map(|c| c.channel.as_str())
+            .collect::<Vec<_>>(),
+        ["unstable", "commit"]
+    );
+    assert_eq!(
+        f.read(".vagrant-cache/regressions"),
+        "pkg:release | 24.09 < 25.01\npkg:lts | garbage < 3.1\n"
+    );
+    assert!(!f.path("p/pkg/last_checked").exists());
+}
+
+#[test]
+fn package_missing_expected_is_an_error() {
+    let f = Fixture::new();
+    f.write(
+        "p/odd/config",
+        "upstream = \"stub/odd\"\n\n[[channels]]\nname = \"nightly\"\nfetch = \"echo 1\"\n",
+    );
+
+    let err = Package::from_name(&f.ctx, "odd").expect_err("package should not form");
+    assert!(err.to_string().contains("Missing expected for nightly"));
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::Fixture;
+use pretty_assertions::assert_eq;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use vagrant::cache::{self, CacheEntry};
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock should be past the epoch")
+        .as_secs()
+}
+
+/// Write a cache entry's metadata and data as the shell library would
+fn entry(f: &Fixture, key: &str, fetched_at: u64, ttl: u64) {
+    let meta = format!(
+        r#"{{ "key": "{key}", "source": "curl", "url": "https://example.com/{key}", "fetched_at": {fetched_at}, "ttl": {ttl} }}"#
+    );
+    f.write(&format!(".vagrant-cache/entries/{key}.json"), &meta);
+    f.write(&format!(".vagrant-cache/entries/{key}"), "data");
+}
+
+fn keys(f: &Fixture) -> Vec<String> {
+    cache::entries(&f.ctx)
+        .expect("entries should be readable")
+        .into_iter()
+        .map(|e| e.key)
+        .collect()
+}
+
+#[test]
+fn entries_expire_once_their_ttl_has_passed() {
+    let entry = CacheEntry {
+        key: "curl-abc".into(),
+        source: "curl".into(),
+        url: "https://example.com".into(),
+        fetched_at: 1000,
+        ttl: 60,
+        etag: None,
+        last_modified: None,
+    };
+
+    assert!(!entry.is_expired(1059));
+    assert!(entry.is_expired(1060));
+    assert_eq!(entry.age(1059), Duration::from_secs(59));
+    // a clock behind the fetch counts as no age at all
+    assert_eq!(entry.age(900), Duration::ZERO);
+}
+
+#[test]
+fn prune_removes_expired_stale_and_orphaned_files() {
+    let f = Fixture::new();
+    let now = now();
+    entry(&f, "curl-fresh", now, 3600);
+    entry(&f, "curl-expired", now - 7200, 3600);
+    f.write(".vagrant-cache/entries/curl-partial.tmp", "");
+    f.write(".vagrant-cache/entries/curl-orphan", "data");
+    f.write(".vagrant-cache/entries/curl-nodata.json", "{}");
+
+    let removed = cache::prune(&f.ctx).expect("prune should succeed");
+
+    assert_eq!(removed, 5);
+    assert_eq!(keys(&f), ["curl-fresh"]);
+    assert!(!f.path(".vagrant-cache/entries/curl-orphan").exists());
+    assert!(!f.path(".vagrant-cache/entries/curl-partial.tmp").exists());
+}
+
+#[test]
+fn clear_removes_every_entry() {
+    let f = Fixture::new();
+    let now = now();
+    entry(&f, "curl-a", now, 3600);
+    entry(&f, "git-b", now, 3600);
+
+    let removed = cache::clear(&f.ctx).expect("clear should succeed");
+
+    assert_eq!(removed, 4);
+    assert!(keys(&f).is_empty());
+    assert!(cache::entries_dir(&f.ctx).is_dir());
+}
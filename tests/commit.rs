@@ -0,0 +1,244 @@
+mod common;
+
+use common::{COMMIT, Fixture, vc};
+use indexmap::IndexMap;
+use pretty_assertions::assert_eq;
+use std::path::Path;
+use std::process::Command;
+use vagrant::commit;
+use vagrant::package::{Package, VersionChannel, bulk};
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let out = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("git should run");
+    assert!(
+        out.status.success(),
+        "git {args:?} failed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8_lossy(&out.stdout).into_owned()
+}
+
+/// Subjects from oldest to newest, excluding those made by hand
+fn subjects(dir: &Path) -> Vec<String> {
+    git(dir, &["log", "--reverse", "--format=%s"])
+        .lines()
+        .filter(|s| s.starts_with("auto") || s.starts_with("pkg"))
+        .map(String::from)
+        .collect()
+}
+
+fn write(f: &Fixture, versions: &[(&Package, Vec<VersionChannel>)]) {
+    let map = versions
+        .iter()
+        .map(|(p, v)| ((*p).clone(), v.clone()))
+        .collect::<IndexMap<_, _>>();
+    bulk::write_all(&f.ctx, &map).expect("write should succeed");
+}
+
+/// Turn the fixture into a git repository with everything committed
+fn init(f: &Fixture) {
+    let root = f.root();
+    f.write(".gitignore", ".vagrant-cache/\n");
+    f.write("runcount", "1");
+    git(root, &["init", "-q", "-b", "master"]);
+    git(root, &["config", "user.name", "a"]);
+    git(root, &["config", "user.email", "a@b"]);
+    git(root, &["config", "commit.gpgsign", "false"]);
+    git(root, &["add", "-A"]);
+    git(root, &["commit", "-q", "-m", "init"]);
+    f.write("runcount", "2");
+}
+
+fn report(f: &Fixture) {
+    for (name, contents) in [
+        ("elapsed", "1s"),
+        ("total", "2"),
+        ("checked", "2"),
+        ("skipped", "0"),
+        ("failed", "0"),
+        ("failed_channels", "bar:unstable\n"),
+    ] {
+        f.write(&format!(".vagrant-cache/{name}"), contents);
+    }
+}
+
+#[test]
+fn commits_channels_packages_and_summary() {
+    let f = Fixture::new();
+    let pkg = f.package(
+        "pkg",
+        &[
+            ("release", "stubrelease"),
+            ("unstable", "stubunstable"),
+            ("commit", "stubcommit"),
+        ],
+    );
+    let bar = f.package("bar", &[("release", "stubrelease")]);
+    let old_commit = "a".repeat(40);
+    write(
+        &f,
+        &[
+            (&pkg, vec![vc("release", "1.0"), vc("commit", &old_commit)]),
+            (&bar, vec![vc("release", "2.0")]),
+        ],
+    );
+    init(&f);
+
+    write(
+        &f,
+        &[
+            (
+                &pkg,
+                vec![
+                    vc("release", "1.1"),
+                    vc("unstable", "1.2-rc1"),
+                    vc("commit", COMMIT),
+                ],
+            ),
+            (&bar, vec![vc("release", "2.0")]),
+        ],
+    );
+    report(&f);
+
+    let commits = commit::commit(&f.ctx, false).expect("commit should succeed");
+    assert_eq!(commits, 6);
+    assert_eq!(
+        subjects(f.root()),
+        vec![
+            "auto(aux): update internal data",
+            "auto(p): pkg:release | 1.0 -> 1.1",
+            "auto(p): pkg:unstable | (none) -> 1.2-rc1",
+            "auto(p): pkg:commit | aaaaaaaa -> 01234567",
+            "auto(p): update versions for pkg",
+            "auto(p): update versions",
+        ]
+    );
+    assert_eq!(git(f.root(), &["status", "--porcelain"]), "");
+
+    // each channel commit holds only its channel
+    assert_eq!(
+        git(f.root(), &["show", "--format=", "--name-only", "HEAD~3"]),
+        "p/pkg/channels/unstable\n"
+    );
+
+    let package = git(f.root(), &["log", "-1", "--format=%b", "HEAD~1"]);
+    let (header, list) = package
+        .split_once("\n\n")
+        .expect("body should have a header");
+    assert!(header.starts_with("[ Vagrant v"), "{header}");
+    assert!(header.ends_with(" | #2 ]"), "{header}");
+    assert_eq!(
+        list.trim_end(),
+        " - pkg:release                             1.0 -> 1.1\n \
+         - pkg:unstable                            (none) -> 1.2-rc1\n \
+         - pkg:commit                              aaaaaaaa -> 01234567"
+    );
+
+    let summary = git(f.root(), &["log", "-1", "--format=%b", "HEAD"]);
+    for expected in [
+        "- Completed in 1s",
+        "- Updated 3 versions for 1 packages:",
+        "    - Release   1\n    - Unstable  1\n    - Commit    1\n    - Other     0",
+        "- Failed to fetch 1 channels:\n    - bar:unstable",
+        "- Pruned 0 stale channels:",
+    ] {
+        assert!(
+            summary.contains(expected),
+            "missing {expected:?} in:\n{summary}"
+        );
+    }
+}
+
+#[test]
+fn pruned_channels_are_committed_with_their_package() {
+    let f = Fixture::new();
+    let pkg = f.package(
+        "pkg",
+        &[("release", "stubrelease"), ("unstable", "stubunstable")],
+    );
+    write(
+        &f,
+        &[(&pkg, vec![vc("release", "1.0"), vc("unstable", "1.1")])],
+    );
+    init(&f);
+
+    let pkg = f.package("pkg", &[("release", "stubrelease")]);
+    git(
+        f.root(),
+        &["commit", "-q", "-m", "drop unstable", "--", "p/pkg/config"],
+    );
+    write(
+        &f,
+        &[(&pkg, vec![vc("release", "1.0"), vc("unstable", "1.1")])],
+    );
+    report(&f);
+
+    commit::commit(&f.ctx, false).expect("commit should succeed");
+    assert_eq!(
+        subjects(f.root()),
+        vec![
+            "auto(aux): update internal data",
+            "auto(p): update versions for pkg",
+            "auto(p): update versions",
+        ]
+    );
+    assert!(!f.path("p/pkg/channels/unstable").exists());
+    assert_eq!(git(f.root(), &["status", "--porcelain"]), "");
+}
+
+#[test]
+fn messages_are_templated() {
+    let mut f = Fixture::new();
+    f.write(
+        "vagrant.toml",
+        "[commit]\nheader = \"run {runcount}\"\nchannel = \"{package}/{channel}: {new}\"\n",
+    );
+    f.reload();
+    let pkg = f.package("pkg", &[("release", "stubrelease")]);
+    write(&f, &[(&pkg, vec![vc("release", "1.0")])]);
+    init(&f);
+
+    write(&f, &[(&pkg, vec![vc("release", "1.1")])]);
+    commit::commit(&f.ctx, false).expect("commit should succeed");
+
+    assert_eq!(subjects(f.root())[1], "pkg/release: 1.1");
+    assert_eq!(
+        git(f.root(), &["log", "-1", "--format=%b", "HEAD~2"]).trim_end(),
+        "run 2"
+    );
+}
+
+#[test]
+fn pushes_unless_told_not_to() {
+    let f = Fixture::new();
+    let remote = tempfile::tempdir().expect("tempdir should be created");
+    git(remote.path(), &["init", "-q", "--bare", "-b", "master"]);
+
+    let pkg = f.package("pkg", &[("release", "stubrelease")]);
+    write(&f, &[(&pkg, vec![vc("release", "1.0")])]);
+    init(&f);
+    let url = remote.path().to_string_lossy();
+    git(f.root(), &["remote", "add", "origin", &url]);
+    git(f.root(), &["push", "-q", "-u", "origin", "master"]);
+
+    write(&f, &[(&pkg, vec![vc("release", "1.1")])]);
+    commit::commit(&f.ctx, false).expect("commit should succeed");
+    let log = |dir: &Path| git(dir, &["log", "-1", "--format=%s", "master"]);
+    assert_eq!(log(remote.path()), "init\n");
+
+    write(&f, &[(&pkg, vec![vc("release", "1.2")])]);
+    commit::commit(&f.ctx, true).expect("commit should succeed");
+    assert_eq!(log(remote.path()), "auto(p): update versions\n");
+}
+
+#[test]
+fn render_fills_placeholders() {
+    assert_eq!(
+        commit::render("{a} {b} {a} {c}", &[("a", "1"), ("b", "2")]),
+        "1 2 1 {c}"
+    );
+}
@@ -0,0 +1,137 @@
+// common/mod.rs
+//
+// Fixtures for building throwaway Vagrant roots
+
+#![allow(dead_code)]
+
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tempfile::TempDir;
+use vagrant::args::Args;
+use vagrant::context::Context;
+use vagrant::package::{Package, VersionChannel};
+
+pub const COMMIT: &str = "0123456789abcdef0123456789abcdef01234567";
+
+/// Stand-in for sh/lib.env that never touches the network
+const STUB_SHLIB: &str = r"
+stubrelease() { printf 'v1.2.3\n'; }
+stubunstable() { printf '1.3.0-rc1\n'; }
+stubcommit() { printf '0123456789abcdef0123456789abcdef01234567\n'; }
+stubfail() { return 1; }
+stubgarbage() { printf 'not a version\n'; }
+";
+
+/// A channel's version, with everything else defaulted
+pub fn vc(channel: &str, version: &str) -> VersionChannel {
+    VersionChannel {
+        channel: channel.into(),
+        version: version.into(),
+        ..Default::default()
+    }
+}
+
+/// A channel's version as changed at an RFC 3339 time
+pub fn version(channel: &str, version: &str, changed_at: &str) -> VersionChannel {
+    VersionChannel {
+        changed_at: Some(changed_at.into()),
+        ..vc(channel, version)
+    }
+}
+
+pub struct Fixture {
+    // held so the directory outlives the fixture
+    _dir: TempDir,
+    pub ctx: Context,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        Self::with_args(Args::default())
+    }
+
+    pub fn with_args(mut args: Args) -> Self {
+        let dir = tempfile::tempdir().expect("tempdir should be created");
+        let root = dir.path().to_path_buf();
+
+        fs::create_dir_all(root.join("p")).expect("p/ should be created");
+        fs::create_dir_all(root.join("sh")).expect("sh/ should be created");
+        fs::create_dir_all(root.join(".vagrant-cache")).expect("cache should be created");
+        fs::write(root.join("sh/lib.env"), STUB_SHLIB).expect("shlib should be written");
+
+        args.root = Some(root);
+        let ctx = Context::new(args).expect("context should form");
+        Self { _dir: dir, ctx }
+    }
+
+    /// Form the context again, picking up a newly written `vagrant.toml`
+    pub fn reload(&mut self) {
+        let args = std::mem::take(&mut self.ctx.args);
+        self.ctx = Context::new(args).expect("context should form");
+    }
+
+    /// Form another context for the same root, for things that take ownership of one
+    pub fn context(&self) -> Context {
+        Context::new(Args {
+            root: Some(self.root().to_path_buf()),
+            ..Default::default()
+        })
+        .expect("context should form")
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.ctx.root
+    }
+
+    pub fn path(&self, rel: &str) -> PathBuf {
+        self.root().join(rel)
+    }
+
+    pub fn write(&self, rel: &str, contents: &str) {
+        let path = self.path(rel);
+        fs::create_dir_all(path.parent().expect("path should have a parent"))
+            .expect("parent should be created");
+        fs::write(path, contents).expect("file should be written");
+    }
+
+    pub fn read(&self, rel: &str) -> String {
+        fs::read_to_string(self.path(rel)).expect("file should be readable")
+    }
+
+    /// Add a package whose channels use the given stub fetch functions
+    pub fn package(&self, name: &str, channels: &[(&str, &str)]) -> Package {
+        self.package_with(name, "", channels)
+    }
+
+    /// Add a package with extra top-level config
+    pub fn package_with(&self, name: &str, extra: &str, channels: &[(&str, &str)]) -> Package {
+        let mut config = format!("upstream = \"stub/{name}\"\n{extra}\n");
+        for (channel, fetch) in channels {
+            let _ = write!(
+                config,
+                "\n[[channels]]\nname = \"{channel}\"\nfetch = \"{fetch}\"\n"
+            );
+        }
+
+        self.write(&format!("p/{name}/config"), &config);
+        Package::from_name(&self.ctx, name).expect("package should form")
+    }
+
+    /// Add a package with a config written verbatim
+    pub fn package_raw(&self, name: &str, config: &str) -> Package {
+        self.write(&format!("p/{name}/config"), config);
+        Package::from_name(&self.ctx, name).expect("package should form")
+    }
+
+    /// Give a package existing versions to fall back on
+    pub fn versions(&self, name: &str, channels: &[(&str, &str)]) {
+        let json = channels
+            .iter()
+            .map(|(c, v)| format!(r#"{{ "channel": "{c}", "version": "{v}" }}"#))
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.write(&format!("p/{name}/versions.json"), &format!("[{json}]"));
+    }
+}
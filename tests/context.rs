@@ -0,0 +1,51 @@
+use pretty_assertions::assert_eq;
+use std::env;
+use std::path::PathBuf;
+use vagrant::args::Args;
+use vagrant::context::Context;
+use vagrant::lock::Lock;
+
+#[test]
+fn paths_default_to_within_root() {
+    let ctx = Context::new(Args {
+        root: Some(PathBuf::from("/srv/vagrant")),
+        ..Default::default()
+    })
+    .expect("context should form");
+
+    assert_eq!(ctx.packages_dir(), PathBuf::from("/srv/vagrant/p"));
+    assert_eq!(ctx.cache, PathBuf::from("/srv/vagrant/.vagrant-cache"));
+    assert_eq!(ctx.shlib, PathBuf::from("/srv/vagrant/sh/lib.env"));
+}
+
+#[test]
+fn paths_are_overridable_and_absolute() {
+    let cwd = env::current_dir().expect("cwd should exist");
+    let ctx = Context::new(Args {
+        root: Some(PathBuf::from("db")),
+        cache_dir: Some(PathBuf::from("/var/cache/vagrant")),
+        shlib: Some(PathBuf::from("lib.env")),
+        ..Default::default()
+    })
+    .expect("context should form");
+
+    assert_eq!(ctx.root, cwd.join("db"));
+    assert_eq!(ctx.cache, PathBuf::from("/var/cache/vagrant"));
+    assert_eq!(ctx.shlib, cwd.join("lib.env"));
+}
+
+#[test]
+fn lock_excludes_concurrent_runs() {
+    let dir = tempfile::tempdir().expect("tempdir should be created");
+    let ctx = Context::new(Args {
+        root: Some(dir.path().to_path_buf()),
+        ..Default::default()
+    })
+    .expect("context should form");
+
+    let lock = Lock::acquire(&ctx).expect("first lock should be acquired");
+    assert!(Lock::acquire(&ctx).is_err());
+
+    drop(lock);
+    assert!(Lock::acquire(&ctx).is_ok());
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use common::Fixture;
+use pretty_assertions::assert_eq;
+use serde_json::Value;
+use std::io::Read;
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use vagrant::daemon::Daemon;
+use vagrant::package::Status;
+use vagrant::package::fetcher::MockFetcher;
+
+const PERIOD: Duration = Duration::from_hours(6);
+
+#[test]
+fn new_packages_are_due_immediately() {
+    let f = Fixture::new();
+    f.package("pkg", &[("release", "stubrelease")]);
+    let fetcher = MockFetcher::default().with("pkg", "release", "1.0");
+    let daemon = Daemon::new(f.context(), &fetcher).expect("daemon should start");
+
+    let now = SystemTime::now();
+    assert_eq!(daemon.tick(now).expect("tick should succeed"), 1);
+    assert_eq!(f.read("p/pkg/channels/release"), "1.0");
+
+    // checked, so not due again until its interval has passed
+    assert_eq!(daemon.tick(now).expect("tick should succeed"), 0);
+    assert_eq!(daemon.tick(now + PERIOD).expect("tick should succeed"), 1);
+    assert_eq!(daemon.status().runs, 2);
+}
+
+#[test]
+fn intervals_follow_chance() {
+    let f = Fixture::new();
+    f.package_with("pkg", "chance = 0.5", &[("release", "stubrelease")]);
+    f.package_with("never", "chance = 0.0", &[("release", "stubrelease")]);
+    f.versions("pkg", &[("release", "1.0")]);
+    f.versions("never", &[("release", "2.0")]);
+    let fetcher = MockFetcher::default().with("pkg", "release", "1.1");
+    let daemon = Daemon::new(f.context(), &fetcher).expect("daemon should start");
+
+    let status = daemon.status();
+    assert_eq!(status.schedule.len(), 1);
+    assert_eq!(status.schedule[0].package, "pkg");
+    assert_eq!(status.schedule[0].interval, "12h");
+
+    // spread over its interval, so it's due by the end of it
+    let now = SystemTime::now();
+    assert_eq!(
+        daemon.tick(now + PERIOD * 2).expect("tick should succeed"),
+        1
+    );
+    assert_eq!(f.read("p/pkg/channels/release"), "1.1");
+
+    // packages that weren't due are still written
+    assert_eq!(
+        f.read("p/ALL.txt"),
+        "never\trelease\t2.0\npkg\trelease\t1.1\n"
+    );
+}
+
+#[test]
+fn status_is_served_over_the_socket() {
+    let f = Fixture::new();
+    f.package("pkg", &[("release", "stubrelease")]);
+    let fetcher = MockFetcher::default().with("pkg", "release", "1.0");
+    let daemon = Daemon::new(f.context(), &fetcher).expect("daemon should start");
+    let socket = daemon.socket();
+
+    let status = thread::scope(|s| {
+        let runner = s.spawn(|| daemon.run());
+
+        // the first tick checks the new package
+        let status = loop {
+            if let Ok(mut stream) = UnixStream::connect(&socket) {
+                let mut body = String::new();
+                stream
+                    .read_to_string(&mut body)
+                    .expect("status should be readable");
+                let status: Value = serde_json::from_str(&body).expect("status should be JSON");
+                if status["runs"] == 1 {
+                    break status;
+                }
+            }
+            thread::sleep(Duration::from_millis(20));
+        };
+
+        daemon.stop();
+        runner
+            .join()
+            .expect("daemon should not panic")
+            .expect("daemon should run");
+        status
+    });
+
+    assert_eq!(status["last_run"]["packages"][0], "pkg");
+    assert_eq!(status["schedule"][0]["package"], "pkg");
+    assert!(!socket.exists());
+}
+
+#[test]
+fn packages_are_due_an_interval_after_their_last_check() {
+    let f = Fixture::new();
+    f.package_with("pkg", "interval = \"7d\"", &[("release", "stubrelease")]);
+    f.versions("pkg", &[("release", "1.0")]);
+    f.write("p/pkg/last_checked", "2025-01-01T00:00:00Z");
+    let fetcher = MockFetcher::default();
+    let daemon = Daemon::new(f.context(), &fetcher).expect("daemon should start");
+
+    let status = daemon.status();
+    assert_eq!(status.schedule[0].interval, "7days");
+    assert_eq!(status.schedule[0].next_at, "2025-01-08T00:00:00Z");
+}
+
+#[test]
+fn packages_that_were_not_due_are_left_unchecked() {
+    let f = Fixture::new();
+    f.package("pkg", &[("release", "stubrelease")]);
+    let other = f.package_with("other", "interval = \"7d\"", &[("release", "stubrelease")]);
+    f.write(
+        "p/other/versions.json",
+        r#"[{ "channel": "release", "version": "2.0", "status": "fresh" }]"#,
+    );
+    // checked a day ago, so not due for another six
+    let yesterday = SystemTime::now() - Duration::from_hours(24);
+    let last_checked = humantime::format_rfc3339_seconds(yesterday).to_string();
+    f.write("p/other/last_checked", &last_checked);
+    let fetcher = MockFetcher::default().with("pkg", "release", "1.0");
+    let daemon = Daemon::new(f.context(), &fetcher).expect("daemon should start");
+
+    let now = SystemTime::now();
+    assert_eq!(daemon.tick(now).expect("tick should succeed"), 1);
+    assert_eq!(f.read("p/pkg/channels/release"), "1.0");
+
+    assert_eq!(f.read("p/other/last_checked"), last_checked);
+    let versions = other
+        .read_previous_versions(&f.ctx)
+        .expect("versions should be readable")
+        .expect("versions should exist");
+    assert_eq!(versions[0].version, "2.0");
+    assert_eq!(versions[0].status, Some(Status::Fresh));
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::Fixture;
+use pretty_assertions::assert_eq;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use vagrant::package::Package;
+use vagrant::package::engine::{Engine, Event, Observer, Outcome, Progress, Quiet};
+
+fn packages(f: &Fixture, names: &[&str]) -> Vec<Package> {
+    names
+        .iter()
+        .map(|name| f.package(name, &[("release", "stubrelease")]))
+        .collect()
+}
+
+/// Records each event as `<start|finish> <package> <completed> <in flight> <failed>`
+#[derive(Default)]
+struct Recorder {
+    events: Mutex<Vec<String>>,
+}
+
+impl Observer for Recorder {
+    fn event(&self, event: &Event<'_>, progress: &Progress) {
+        let (kind, package) = match event {
+            Event::Started(package) => ("start", package),
+            Event::Finished { package, .. } => ("finish", package),
+        };
+        self.events
+            .lock()
+            .expect("lock should not be poisoned")
+            .push(format!(
+                "{kind} {} {} {} {}",
+                package.name, progress.completed, progress.in_flight, progress.failed
+            ));
+    }
+}
+
+#[test]
+fn runs_are_bounded_and_ordered() {
+    let f = Fixture::new();
+    let packages = packages(&f, &["a", "b", "c", "d", "e", "f", "g", "h"]);
+    let cancel = AtomicBool::new(false);
+    let engine = Engine::new(3, &Quiet, &cancel);
+
+    let (in_flight, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+    let results = engine.run(&packages, |package| {
+        let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        most.fetch_max(now, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(20));
+        in_flight.fetch_sub(1, Ordering::SeqCst);
+        (package.name.clone(), Outcome::Checked)
+    });
+
+    assert_eq!(most.load(Ordering::SeqCst), 3);
+    let names = results
+        .into_iter()
+        .map(|r| r.expect("every package should run").value)
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["a", "b", "c", "d", "e", "f", "g", "h"]);
+}
+
+#[test]
+fn cancelled_runs_take_on_no_more_packages() {
+    let f = Fixture::new();
+    let packages = packages(&f, &["a", "b", "c", "d"]);
+    let cancel = AtomicBool::new(false);
+    let engine = Engine::new(1, &Quiet, &cancel);
+
+    let results = engine.run(&packages, |package| {
+        cancel.store(true, Ordering::Relaxed);
+        (package.name.clone(), Outcome::Checked)
+    });
+
+    let ran = results
+        .iter()
+        .map(|r| r.as_ref().map(|d| d.value.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(ran, [Some("a"), None, None, None]);
+}
+
+#[test]
+fn observers_see_progress() {
+    let f = Fixture::new();
+    let packages = packages(&f, &["a", "b"]);
+    let cancel = AtomicBool::new(false);
+    let recorder = Recorder::default();
+    let engine = Engine::new(1, &recorder, &cancel);
+
+    engine.run(&packages, |package| {
+        let outcome = if package.name == "a" {
+            Outcome::Failed
+        } else {
+            Outcome::Checked
+        };
+        ((), outcome)
+    });
+
+    assert_eq!(
+        recorder
+            .events
+            .into_inner()
+            .expect("lock should not be poisoned"),
+        [
+            "start a 0 1 0",
+            "finish a 1 0 1",
+            "start b 1 1 1",
+            "finish b 2 0 1"
+        ]
+    );
+}
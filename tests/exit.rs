@@ -0,0 +1,76 @@
+mod common;
+
+use common::Fixture;
+use pretty_assertions::assert_eq;
+use vagrant::args::Args;
+use vagrant::exit::{self, Exit};
+
+/// A fixture whose last run checked `checked` packages and failed `failed`
+fn run(args: Args, checked: usize, failed: &[&str]) -> Fixture {
+    let f = Fixture::with_args(args);
+    f.write(".vagrant-cache/checked", &checked.to_string());
+    f.write(".vagrant-cache/failed", &failed.len().to_string());
+    let list = failed.iter().fold(String::new(), |acc, p| acc + p + "\n");
+    f.write(".vagrant-cache/failed_packages", &list);
+    f
+}
+
+fn judge(f: &Fixture) -> Exit {
+    exit::judge(&f.ctx).expect("judge should succeed")
+}
+
+#[test]
+fn runs_without_failures_succeed() {
+    let f = run(Args::default(), 10, &[]);
+    assert_eq!(judge(&f), Exit::Success);
+}
+
+#[test]
+fn failures_are_reported_without_a_threshold() {
+    let f = run(Args::default(), 1, &["a", "b"]);
+    assert_eq!(judge(&f), Exit::Failures);
+}
+
+#[test]
+fn regressions_are_failures() {
+    let f = run(Args::default(), 10, &[]);
+    f.write(".vagrant-cache/regressions", "pkg:release | 1.0 < 2.0\n");
+    assert_eq!(judge(&f), Exit::Failures);
+}
+
+#[test]
+fn failure_rates_over_the_threshold_exceed_it() {
+    let args = |rate| Args {
+        max_failure_rate: Some(rate),
+        ..Default::default()
+    };
+
+    // one of twenty is 5%, which is allowed
+    assert_eq!(judge(&run(args(0.05), 19, &["a"])), Exit::Failures);
+    assert_eq!(judge(&run(args(0.05), 9, &["a"])), Exit::Threshold);
+    assert_eq!(judge(&run(args(0.0), 19, &[])), Exit::Success);
+}
+
+#[test]
+fn failed_packages_can_exceed_the_threshold_alone() {
+    let args = || Args {
+        fail_on: vec!["glibc".into()],
+        ..Default::default()
+    };
+
+    assert_eq!(judge(&run(args(), 99, &["zlib"])), Exit::Failures);
+    assert_eq!(judge(&run(args(), 99, &["glibc"])), Exit::Threshold);
+}
+
+#[test]
+fn codes_are_distinct() {
+    let codes = [
+        Exit::Success,
+        Exit::Error,
+        Exit::Config,
+        Exit::Failures,
+        Exit::Threshold,
+    ]
+    .map(Exit::code);
+    assert_eq!(codes, [0, 1, 2, 3, 4]);
+}
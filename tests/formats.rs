@@ -0,0 +1,156 @@
+mod common;
+
+use common::Fixture;
+use indexmap::IndexMap;
+use pretty_assertions::assert_eq;
+use vagrant::args::Args;
+use vagrant::package::formats::Format;
+use vagrant::package::{Package, Status, VersionChannel, bulk};
+
+fn all_formats() -> Fixture {
+    Fixture::with_args(Args {
+        formats: vec![
+            Format::Json,
+            Format::Txt,
+            Format::Toml,
+            Format::Csv,
+            Format::Ndjson,
+            Format::Sqlite,
+        ],
+        ..Default::default()
+    })
+}
+
+fn map(f: &Fixture, release: &str) -> IndexMap<Package, Vec<VersionChannel>> {
+    let pkg = f.package("foo", &[("release", "stubrelease")]);
+    let bar = f.package("bar", &[("release", "stubrelease")]);
+
+    let mut map = IndexMap::new();
+    map.insert(
+        bar,
+        vec![VersionChannel {
+            channel: "release".into(),
+            version: "2.0".into(),
+            ..Default::default()
+        }],
+    );
+    map.insert(
+        pkg,
+        vec![VersionChannel {
+            channel: "release".into(),
+            version: release.into(),
+            changed_at: Some(format!("2025-01-01T00:00:0{}Z", release.len())),
+            status: Some(Status::Fresh),
+            raw: Some(format!("v{release}")),
+            ..Default::default()
+        }],
+    );
+    map
+}
+
+#[test]
+fn only_configured_formats_are_written() {
+    let f = Fixture::new();
+    bulk::write_all(&f.ctx, &map(&f, "1.0")).expect("write should succeed");
+
+    assert!(f.path("p/ALL.json").exists());
+    assert!(f.path("p/ALL.txt").exists());
+    assert!(!f.path("p/ALL.csv").exists());
+    assert!(!f.path("p/ALL.sqlite").exists());
+}
+
+#[test]
+fn formats_are_selectable_by_config() {
+    let mut f = Fixture::new();
+    f.write("vagrant.toml", "[output]\nformats = [\"csv\"]\n");
+    f.reload();
+    bulk::write_all(&f.ctx, &map(&f, "1.0")).expect("write should succeed");
+
+    assert!(f.path("p/ALL.csv").exists());
+    assert!(!f.path("p/ALL.json").exists());
+    assert!(!f.path("p/ALL.schema.json").exists());
+}
+
+#[test]
+fn toml_nests_packages() {
+    let f = all_formats();
+    bulk::write_all(&f.ctx, &map(&f, "1.0")).expect("write should succeed");
+
+    let all: toml::Value = toml::from_str(&f.read("p/ALL.toml")).expect("ALL.toml should parse");
+    assert_eq!(all["packages"][1]["package"].as_str(), Some("foo"));
+    assert_eq!(
+        all["packages"][1]["versions"][0]["version"].as_str(),
+        Some("1.0")
+    );
+}
+
+#[test]
+fn csv_has_a_header_and_a_row_per_channel() {
+    let f = all_formats();
+    bulk::write_all(&f.ctx, &map(&f, "1.0")).expect("write should succeed");
+
+    assert_eq!(
+        f.read("p/ALL.csv"),
+        "package,channel,version,frozen,fetched_at,changed_at,source,status,raw\n\
+        bar,release,2.0,false,,,,,\n\
+        foo,release,1.0,false,,2025-01-01T00:00:03Z,,fresh,v1.0\n"
+    );
+}
+
+#[test]
+fn ndjson_has_a_package_per_line() {
+    let f = all_formats();
+    bulk::write_all(&f.ctx, &map(&f, "1.0")).expect("write should succeed");
+
+    let lines = f
+        .read("p/ALL.ndjson")
+        .lines()
+        .map(|l| serde_json::from_str::<serde_json::Value>(l).expect("line should parse"))
+        .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["package"], "bar");
+    assert_eq!(lines[1]["versions"][0]["raw"], "v1.0");
+}
+
+#[test]
+fn sqlite_keeps_history_across_runs() {
+    let f = all_formats();
+    bulk::write_all(&f.ctx, &map(&f, "1.0")).expect("write should succeed");
+    bulk::write_all(&f.ctx, &map(&f, "1.0")).expect("write should succeed");
+    bulk::write_all(&f.ctx, &map(&f, "1.0.1")).expect("write should succeed");
+
+    let conn = rusqlite::Connection::open(f.path("p/ALL.sqlite")).expect("db should open");
+
+    let packages = conn
+        .prepare("SELECT name FROM packages ORDER BY name")
+        .and_then(|mut s| {
+            s.query_map([], |r| r.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .expect("packages should be queryable");
+    assert_eq!(packages, ["bar", "foo"]);
+
+    let version: String = conn
+        .query_row(
+            "SELECT version FROM channels WHERE package = 'foo' AND channel = 'release'",
+            [],
+            |r| r.get(0),
+        )
+        .expect("channel should be queryable");
+    assert_eq!(version, "1.0.1");
+
+    let history = conn
+        .prepare("SELECT version, seen_at FROM history WHERE package = 'foo' ORDER BY rowid")
+        .and_then(|mut s| {
+            s.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))?
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .expect("history should be queryable");
+    assert_eq!(
+        history,
+        [
+            ("1.0".to_string(), "2025-01-01T00:00:03Z".to_string()),
+            ("1.0.1".to_string(), "2025-01-01T00:00:05Z".to_string()),
+        ]
+    );
+}
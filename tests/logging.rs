@@ -0,0 +1,93 @@
+mod common;
+
+use common::Fixture;
+use pretty_assertions::assert_eq;
+use serde_json::Value;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::Registry;
+use tracing_subscriber::fmt::writer::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use vagrant::logging::{self, LogFormat};
+use vagrant::package::fetcher::Dispatcher;
+
+/// Collects everything logged to it
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Buffer {
+    fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().expect("lock should not be poisoned")).into_owned()
+    }
+}
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .expect("lock should not be poisoned")
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl MakeWriter<'_> for Buffer {
+    type Writer = Self;
+
+    fn make_writer(&self) -> Self {
+        self.clone()
+    }
+}
+
+/// Fetch a package's channels, returning what was logged
+fn fetch(format: LogFormat, ansi: bool) -> String {
+    let f = Fixture::new();
+    let pkg = f.package(
+        "pkg",
+        &[("release", "stubrelease"), ("unstable", "stubfail")],
+    );
+
+    let buffer = Buffer::default();
+    let subscriber = Registry::default().with(logging::layer(format, buffer.clone(), ansi));
+    tracing::subscriber::with_default(subscriber, || pkg.fetch_channels(&f.ctx, &Dispatcher));
+    buffer.contents()
+}
+
+#[test]
+fn json_logs_close_channel_spans_with_their_outcome() {
+    let lines = fetch(LogFormat::Json, false)
+        .lines()
+        .map(|l| serde_json::from_str::<Value>(l).expect("every line should be json"))
+        .collect::<Vec<_>>();
+
+    let closed = lines
+        .iter()
+        .filter(|l| l["fields"]["message"] == "close")
+        .map(|l| {
+            let span = &l["span"];
+            assert!(span["duration"].is_f64(), "{span}");
+            (
+                span["channel"].as_str().unwrap_or_default(),
+                span["outcome"].as_str().unwrap_or_default(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(closed, [("release", "fresh"), ("unstable", "fallback")]);
+
+    let error = lines
+        .iter()
+        .find(|l| l["level"] == "ERROR")
+        .expect("the failed channel should be logged");
+    assert_eq!(error["span"]["channel"], "unstable");
+}
+
+#[test]
+fn logs_without_ansi_have_no_escapes() {
+    let logged = fetch(LogFormat::Compact, false);
+    assert!(logged.contains("channel=unstable"), "{logged}");
+    assert!(!logged.contains('\x1b'), "{logged}");
+}
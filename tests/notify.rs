@@ -0,0 +1,186 @@
+mod common;
+
+use common::{Fixture, vc};
+use indexmap::IndexMap;
+use pretty_assertions::assert_eq;
+use serde_json::Value;
+use std::io::Read;
+use std::os::unix::net::UnixListener;
+use std::process::Command;
+use std::{fs, thread};
+use vagrant::notify;
+use vagrant::package::{Package, VersionChange, bulk};
+
+/// A fixture with `vagrant.toml` configuring notifiers
+fn fixture(notify: &str) -> Fixture {
+    let mut f = Fixture::new();
+    f.write("vagrant.toml", notify);
+    f.reload();
+    f
+}
+
+/// Write an update to `lfs`-tagged `pkg`, adding a channel, and an unchanged `bar`
+fn changes(f: &Fixture) -> Vec<VersionChange> {
+    let pkg = f.package_with(
+        "pkg",
+        "tags = [\"lfs\"]",
+        &[("release", "stubrelease"), ("unstable", "stubunstable")],
+    );
+    let bar = f.package("bar", &[("release", "stubrelease")]);
+    f.versions("pkg", &[("release", "1.0")]);
+    f.versions("bar", &[("release", "2.0")]);
+
+    let mut map = IndexMap::<Package, _>::new();
+    map.insert(pkg, vec![vc("release", "1.1"), vc("unstable", "1.2-rc1")]);
+    map.insert(bar, vec![vc("release", "2.0")]);
+    bulk::write_all(&f.ctx, &map).expect("write should succeed")
+}
+
+fn payload(raw: &str) -> Vec<(String, String)> {
+    let json: Value = serde_json::from_str(raw).expect("payload should be JSON");
+    json["changes"]
+        .as_array()
+        .expect("changes should be an array")
+        .iter()
+        .map(|c| {
+            let field = |k: &str| c[k].as_str().unwrap_or_default().to_string();
+            (
+                format!("{}:{}", field("package"), field("channel")),
+                field("new"),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn write_all_returns_changed_and_new_channels() {
+    let f = Fixture::new();
+    let changes = changes(&f);
+
+    assert_eq!(
+        changes,
+        vec![
+            VersionChange {
+                package: "pkg".into(),
+                channel: "release".into(),
+                old: Some("1.0".into()),
+                new: "1.1".into(),
+                tags: vec!["lfs".into()],
+            },
+            VersionChange {
+                package: "pkg".into(),
+                channel: "unstable".into(),
+                old: None,
+                new: "1.2-rc1".into(),
+                tags: vec!["lfs".into()],
+            },
+        ]
+    );
+}
+
+#[test]
+fn webhook_receives_changes() {
+    let http = tiny_http::Server::http("127.0.0.1:0").expect("listener should bind");
+    let addr = http
+        .server_addr()
+        .to_ip()
+        .expect("listener should have an address");
+    let f = fixture(&format!("[[notify]]\nwebhook = \"http://{addr}/hook\"\n"));
+    let changes = changes(&f);
+
+    let received = thread::scope(|s| {
+        let server = s.spawn(|| {
+            let mut request = http.recv().expect("webhook should be called");
+            let mut body = String::new();
+            request
+                .as_reader()
+                .read_to_string(&mut body)
+                .expect("body should be readable");
+            let method = request.method().to_string();
+            let url = request.url().to_string();
+            request
+                .respond(tiny_http::Response::empty(204))
+                .expect("response should be sent");
+            (method, url, body)
+        });
+
+        assert_eq!(notify::notify_all(&f.ctx, &changes), 0);
+        server.join().expect("server should not panic")
+    });
+
+    let (method, url, body) = received;
+    assert_eq!(method, "POST");
+    assert_eq!(url, "/hook");
+    assert_eq!(
+        payload(&body),
+        vec![
+            ("pkg:release".to_string(), "1.1".to_string()),
+            ("pkg:unstable".to_string(), "1.2-rc1".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn hooks_are_filtered_by_tag() {
+    let f = fixture(
+        "[[notify]]\nhook = \"cat > lfs.json\"\ntags = [\"lfs\"]\n\n\
+        [[notify]]\nhook = \"cat > other.json\"\ntags = [\"other\"]\n",
+    );
+    let changes = changes(&f);
+
+    assert_eq!(notify::notify_all(&f.ctx, &changes), 0);
+    assert_eq!(payload(&f.read("lfs.json")).len(), 2);
+    assert!(!f.path("other.json").exists());
+}
+
+#[test]
+fn socket_receives_changes() {
+    let f = fixture("[[notify]]\nsocket = \"notify.sock\"\n");
+    let listener = UnixListener::bind(f.path("notify.sock")).expect("socket should bind");
+    let changes = changes(&f);
+
+    assert_eq!(notify::notify_all(&f.ctx, &changes), 0);
+
+    let (mut stream, _) = listener.accept().expect("notifier should connect");
+    let mut body = String::new();
+    stream
+        .read_to_string(&mut body)
+        .expect("payload should be readable");
+    assert_eq!(payload(&body).len(), 2);
+}
+
+#[test]
+fn pipe_receives_changes_only_with_a_reader() {
+    let f = fixture("[[notify]]\npipe = \"notify.fifo\"\n");
+    let status = Command::new("mkfifo")
+        .arg(f.path("notify.fifo"))
+        .status()
+        .expect("mkfifo should run");
+    assert!(status.success());
+    let changes = changes(&f);
+
+    // nothing is reading, so it fails rather than blocking
+    assert_eq!(notify::notify_all(&f.ctx, &changes), 1);
+
+    let body = thread::scope(|s| {
+        let reader = s.spawn(|| fs::read_to_string(f.path("notify.fifo")));
+
+        // the reader may not have opened the pipe yet
+        while notify::notify_all(&f.ctx, &changes) != 0 {
+            thread::yield_now();
+        }
+        reader.join().expect("reader should not panic")
+    })
+    .expect("pipe should be readable");
+
+    assert_eq!(payload(&body).len(), 2);
+}
+
+#[test]
+fn failing_notifiers_are_counted() {
+    let f = fixture("[[notify]]\nhook = \"exit 1\"\n\n[[notify]]\nhook = \"true\"\n");
+    let changes = changes(&f);
+
+    assert_eq!(notify::notify_all(&f.ctx, &changes), 1);
+    assert_eq!(notify::notify_all(&f.ctx, &[]), 0);
+}
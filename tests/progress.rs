@@ -0,0 +1,39 @@
+use pretty_assertions::assert_eq;
+use std::time::{Duration, Instant};
+use vagrant::package::engine::Progress;
+use vagrant::progress;
+
+fn progress(completed: usize, total: usize) -> Progress {
+    Progress {
+        total,
+        completed,
+        in_flight: 3,
+        failed: 1,
+        started_at: Instant::now()
+            .checked_sub(Duration::from_secs(10))
+            .expect("the clock should be past 10s"),
+    }
+}
+
+#[test]
+fn eta_follows_the_pace_so_far() {
+    assert_eq!(progress(0, 10).eta(), None);
+    assert_eq!(progress(5, 10).eta(), Some(Duration::from_secs(10)));
+    assert_eq!(progress(10, 10).eta(), Some(Duration::ZERO));
+}
+
+#[test]
+fn status_shows_counts_and_the_longest_in_flight() {
+    assert_eq!(
+        progress::status(&progress(5, 10), Some("mesa"), 80),
+        "[ 5/10 50% ] 3 in flight, 1 failed, 10s left, waiting on mesa"
+    );
+    assert_eq!(
+        progress::status(&progress(0, 10), None, 80),
+        "[ 0/10 0% ] 3 in flight, 1 failed, ? left"
+    );
+    assert_eq!(
+        progress::status(&progress(5, 10), Some("mesa"), 12),
+        "[ 5/10 50% ]"
+    );
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use common::Fixture;
+use indexmap::IndexMap;
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+use vagrant::package::fetcher::Dispatcher;
+use vagrant::package::{SCHEMA_VERSION, Status, VersionChannel, bulk};
+use vagrant::schema::{self, SchemaKind};
+
+/// A fixture writing the current schema version
+fn fixture() -> Fixture {
+    let mut f = Fixture::new();
+    f.write(
+        "vagrant.toml",
+        &format!("[output]\nschema = {SCHEMA_VERSION}\n"),
+    );
+    f.reload();
+    f
+}
+
+fn write(f: &Fixture) {
+    let pkg = f.package("foo", &[("release", "stubrelease")]);
+
+    let mut map = IndexMap::new();
+    map.insert(
+        pkg,
+        vec![VersionChannel {
+            channel: "release".into(),
+            version: "1.2.3".into(),
+            fetched_at: Some("2025-01-02T03:04:05Z".into()),
+            changed_at: Some("2025-01-01T00:00:00Z".into()),
+            source: Some("https://github.com/stub/foo.git".into()),
+            status: Some(Status::Fresh),
+            raw: Some("v1.2.3".into()),
+            ..Default::default()
+        }],
+    );
+
+    bulk::write_all(&f.ctx, &map).expect("write should succeed");
+}
+
+fn parse(f: &Fixture, rel: &str) -> Value {
+    serde_json::from_str(&f.read(rel)).expect("file should be valid JSON")
+}
+
+fn expected_entry() -> Value {
+    json!({
+        "package": "foo",
+        "versions": [{
+            "channel": "release",
+            "version": "1.2.3",
+            "fetched_at": "2025-01-02T03:04:05Z",
+            "changed_at": "2025-01-01T00:00:00Z",
+            "source": "https://github.com/stub/foo.git",
+            "status": "fresh",
+            "raw": "v1.2.3"
+        }]
+    })
+}
+
+#[test]
+fn schema_version_is_pinned() {
+    // bumping this means downstream consumers need to be told, and the README updated
+    assert_eq!(SCHEMA_VERSION, 3);
+}
+
+#[test]
+fn versions_json_has_the_documented_shape() {
+    let f = fixture();
+    write(&f);
+
+    let mut expected = expected_entry();
+    expected["schema_version"] = SCHEMA_VERSION.into();
+    assert_eq!(parse(&f, "p/foo/versions.json"), expected);
+}
+
+#[test]
+fn all_json_has_the_documented_shape() {
+    let f = fixture();
+    write(&f);
+
+    assert_eq!(
+        parse(&f, "p/ALL.json"),
+        json!({ "schema_version": SCHEMA_VERSION, "packages": [expected_entry()] })
+    );
+}
+
+#[test]
+fn older_shapes_are_written_unless_opted_into() {
+    let f = Fixture::new();
+    write(&f);
+
+    let entry = expected_entry();
+    assert_eq!(parse(&f, "p/foo/versions.json"), entry["versions"]);
+    assert_eq!(parse(&f, "p/ALL.json"), json!([entry]));
+    assert!(!f.path("p/ALL.schema.json").exists());
+}
+
+#[test]
+fn schemas_are_written_alongside_all_json() {
+    let f = fixture();
+    write(&f);
+
+    for kind in SchemaKind::ALL {
+        let rel = format!("p/{}", kind.file_name());
+        assert_eq!(f.read(&rel), schema::generate(kind));
+    }
+
+    let all = parse(&f, "p/ALL.schema.json");
+    assert_eq!(all["type"], "object");
+    assert_eq!(all["required"], json!(["schema_version", "packages"]));
+    assert_eq!(
+        all["properties"]["packages"]["items"]["$ref"],
+        "#/$defs/PackageVersions"
+    );
+    assert_eq!(
+        all["$defs"]["PackageVersions"]["required"],
+        json!(["package", "versions"])
+    );
+
+    let versions = parse(&f, "p/versions.schema.json");
+    assert_eq!(
+        versions["required"],
+        json!(["schema_version", "package", "versions"])
+    );
+    for schema in [&all, &versions] {
+        let property = &schema["properties"]["schema_version"];
+        assert_eq!(property["type"], "integer");
+        assert_eq!(property["const"], SCHEMA_VERSION);
+    }
+
+    let properties = versions["$defs"]["VersionChannel"]["properties"]
+        .as_object()
+        .expect("VersionChannel should have properties");
+    assert_eq!(
+        properties.keys().collect::<Vec<_>>(),
+        [
+            "changed_at",
+            "channel",
+            "fetched_at",
+            "frozen",
+            "raw",
+            "source",
+            "status",
+            "version"
+        ]
+    );
+    assert_eq!(
+        versions["$defs"]["VersionChannel"]["required"],
+        json!(["channel", "version"])
+    );
+}
+
+#[test]
+fn every_schema_version_can_be_read_back() {
+    let f = Fixture::new();
+    let pkg = f.package_with("foo", "chance = 0.0", &[("release", "stubrelease")]);
+
+    for versions in [
+        json!([{ "channel": "release", "version": "1.0" }]),
+        json!({ "schema_version": 3, "package": "foo", "versions": [{ "channel": "release", "version": "1.0" }] }),
+    ] {
+        f.write("p/foo/versions.json", &versions.to_string());
+
+        let map = bulk::fetch_all(&f.ctx, std::slice::from_ref(&pkg), &Dispatcher)
+            .expect("fetch should succeed");
+        assert_eq!(map[&pkg][0].version, "1.0");
+    }
+}
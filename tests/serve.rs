@@ -0,0 +1,218 @@
+mod common;
+
+use common::{Fixture, version};
+use indexmap::IndexMap;
+use pretty_assertions::assert_eq;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use vagrant::package::bulk;
+use vagrant::serve::Server;
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+fn get(addr: SocketAddr, path: &str, headers: &[(&str, &str)]) -> Response {
+    let mut stream = TcpStream::connect(addr).expect("server should accept connections");
+    let mut request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n");
+    for (k, v) in headers {
+        let _ = write!(request, "{k}: {v}\r\n");
+    }
+    request += "\r\n";
+    stream
+        .write_all(request.as_bytes())
+        .expect("request should be sent");
+
+    let mut raw = String::new();
+    stream
+        .read_to_string(&mut raw)
+        .expect("response should be read");
+
+    let (head, body) = raw
+        .split_once("\r\n\r\n")
+        .expect("response should have a head");
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|l| l.split(' ').nth(1))
+        .and_then(|s| s.parse().ok())
+        .expect("response should have a status");
+    let headers = lines
+        .filter_map(|l| l.split_once(": "))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+    Response {
+        status,
+        headers,
+        body: body.to_string(),
+    }
+}
+
+fn fixture() -> Fixture {
+    let f = Fixture::new();
+    write(&f);
+    f
+}
+
+fn write(f: &Fixture) {
+    let pkg = f.package_with(
+        "foo",
+        "tags = [\"core\"]",
+        &[("release", "stubrelease"), ("commit", "stubcommit")],
+    );
+    let other = f.package("bar", &[("release", "stubrelease")]);
+
+    let mut map = IndexMap::new();
+    map.insert(
+        other,
+        vec![version("release", "2.0", "2025-01-01T00:00:00Z")],
+    );
+    map.insert(
+        pkg,
+        vec![
+            version("release", "1.0", "2025-03-01T00:00:00Z"),
+            version("commit", "abc", "2025-01-01T00:00:00Z"),
+        ],
+    );
+    bulk::write_all(&f.ctx, &map).expect("write should succeed");
+}
+
+/// Run a server on an ephemeral port for the duration of `f`
+fn serve(fixture: &Fixture, f: impl FnOnce(SocketAddr)) {
+    let server = Server::bind(&fixture.ctx, "127.0.0.1:0").expect("server should bind");
+    let addr = server.addr().expect("server should have an address");
+
+    thread::scope(|s| {
+        s.spawn(|| server.run());
+        f(addr);
+        server.stop();
+    });
+}
+
+#[test]
+fn serve_mirrors_the_database_layout() {
+    let f = fixture();
+    serve(&f, |addr| {
+        let res = get(addr, "/p/foo/channels/release", &[]);
+        assert_eq!(res.status, 200);
+        assert_eq!(res.body, f.read("p/foo/channels/release"));
+
+        let res = get(addr, "/p/foo/versions.json", &[]);
+        assert_eq!(res.status, 200);
+        assert_eq!(res.header("Content-Type"), Some("application/json"));
+        assert_eq!(res.body, f.read("p/foo/versions.json") + "\n");
+
+        let res = get(addr, "/p/foo/versions.txt", &[]);
+        assert_eq!(res.body, f.read("p/foo/versions.txt"));
+
+        let res = get(addr, "/ALL.json", &[]);
+        assert_eq!(res.body, f.read("p/ALL.json") + "\n");
+
+        assert_eq!(get(addr, "/ALL.txt", &[]).body, f.read("p/ALL.txt"));
+    });
+}
+
+#[test]
+fn serve_follows_the_configured_schema_version() {
+    let mut f = fixture();
+    f.write("vagrant.toml", "[output]\nschema = 3\n");
+    f.reload();
+    write(&f);
+
+    serve(&f, |addr| {
+        let res = get(addr, "/p/foo/versions.json", &[]);
+        assert_eq!(res.body, f.read("p/foo/versions.json") + "\n");
+        let json: serde_json::Value = serde_json::from_str(&res.body).expect("body should parse");
+        assert_eq!(json["versions"][1]["version"], "abc");
+
+        let res = get(addr, "/ALL.json", &[]);
+        assert_eq!(res.body, f.read("p/ALL.json") + "\n");
+    });
+}
+
+#[test]
+fn serve_rejects_missing_and_escaping_paths() {
+    let f = fixture();
+    serve(&f, |addr| {
+        assert_eq!(get(addr, "/p/nope/versions.json", &[]).status, 404);
+        assert_eq!(get(addr, "/p/foo/channels/nope", &[]).status, 404);
+        assert_eq!(get(addr, "/p/..%2Fp%2Ffoo/versions.json", &[]).status, 404);
+        assert_eq!(get(addr, "/p/../versions.json", &[]).status, 404);
+        assert_eq!(get(addr, "/query?nope=1", &[]).status, 400);
+    });
+}
+
+#[test]
+fn serve_filters_queries() {
+    let f = fixture();
+    serve(&f, |addr| {
+        let query = |q: &str| -> serde_json::Value {
+            let res = get(addr, &format!("/query?{q}"), &[]);
+            assert_eq!(res.status, 200);
+            serde_json::from_str(&res.body).expect("body should parse")
+        };
+
+        let json = query("channel=commit");
+        assert_eq!(json.as_array().map(Vec::len), Some(1));
+        assert_eq!(json[0]["versions"][0]["version"], "abc");
+
+        let json = query("tag=core");
+        assert_eq!(json.as_array().map(Vec::len), Some(1));
+        assert_eq!(json[0]["package"], "foo");
+
+        let json = query("changed_since=2025-02-01T00:00:00Z");
+        assert_eq!(json.as_array().map(Vec::len), Some(1));
+        assert_eq!(json[0]["versions"].as_array().map(Vec::len), Some(1));
+        assert_eq!(json[0]["versions"][0]["channel"], "release");
+
+        assert_eq!(
+            query("tag=core&channel=release&changed_since=2026-01-01T00:00:00Z"),
+            serde_json::json!([])
+        );
+    });
+}
+
+#[test]
+fn serve_honors_conditional_requests() {
+    let f = fixture();
+    serve(&f, |addr| {
+        let res = get(addr, "/p/foo/versions.json", &[]);
+        let etag = res.header("ETag").expect("ETag should be set").to_string();
+        let last_modified = res
+            .header("Last-Modified")
+            .expect("Last-Modified should be set")
+            .to_string();
+
+        let res = get(addr, "/p/foo/versions.json", &[("If-None-Match", &etag)]);
+        assert_eq!(res.status, 304);
+        assert_eq!(res.body, "");
+
+        let res = get(
+            addr,
+            "/p/foo/versions.json",
+            &[("If-Modified-Since", &last_modified)],
+        );
+        assert_eq!(res.status, 304);
+
+        let res = get(
+            addr,
+            "/p/foo/versions.json",
+            &[("If-None-Match", "\"stale\"")],
+        );
+        assert_eq!(res.status, 200);
+    });
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use common::{Fixture, version};
+use indexmap::IndexMap;
+use pretty_assertions::assert_eq;
+use vagrant::args::Args;
+use vagrant::package::bulk;
+use vagrant::package::formats::Format;
+use vagrant::site;
+
+#[test]
+fn site_renders_every_package() {
+    let f = Fixture::new();
+    let pkg = f.package("foo", &[("release", "stubrelease")]);
+    let other = f.package("a<b", &[("release", "stubrelease")]);
+
+    let mut map = IndexMap::new();
+    map.insert(
+        other,
+        vec![version("release", "2.0", "2025-01-01T00:00:00Z")],
+    );
+    map.insert(pkg, vec![version("release", "1.0", "2025-02-01T00:00:00Z")]);
+    bulk::write_all(&f.ctx, &map).expect("write should succeed");
+
+    let out = f.path("site");
+    let pages = site::build(&f.ctx, &out, "https://example.org/").expect("site should build");
+    assert_eq!(pages, 3);
+
+    let index = f.read("site/index.html");
+    assert!(index.contains(r#"<a href="p/foo/">foo</a>"#));
+    assert!(index.contains("a&lt;b"));
+    assert!(!index.contains("a<b"));
+    assert!(f.path("site/p/foo/index.html").exists());
+
+    // newest first, with absolute links
+    let atom = f.read("site/atom.xml");
+    let foo = atom
+        .find("foo:release 1.0")
+        .expect("foo should be in the feed");
+    let other = atom
+        .find("a&lt;b:release 2.0")
+        .expect("a<b should be in the feed");
+    assert!(foo < other);
+    assert!(atom.contains(r#"<link href="https://example.org/p/foo/"/>"#));
+    assert!(atom.contains("<updated>2025-02-01T00:00:00Z</updated>"));
+
+    let rss = f.read("site/p/foo/rss.xml");
+    assert!(rss.contains("<pubDate>Sat, 01 Feb 2025 00:00:00 GMT</pubDate>"));
+    assert!(!rss.contains("a&lt;b"));
+}
+
+#[test]
+fn site_renders_history_from_sqlite() {
+    let f = Fixture::with_args(Args {
+        formats: vec![Format::Json, Format::Sqlite],
+        ..Default::default()
+    });
+    let pkg = f.package("foo", &[("release", "stubrelease")]);
+
+    for (v, at) in [
+        ("1.0", "2025-01-01T00:00:00Z"),
+        ("1.1", "2025-02-01T00:00:00Z"),
+    ] {
+        let mut map = IndexMap::new();
+        map.insert(pkg.clone(), vec![version("release", v, at)]);
+        bulk::write_all(&f.ctx, &map).expect("write should succeed");
+    }
+
+    site::build(&f.ctx, &f.path("site"), "https://example.org/versions")
+        .expect("site should build");
+
+    let page = f.read("site/p/foo/index.html");
+    let new = page
+        .find("<td>2025-02-01T00:00:00Z</td><td>release</td><td><code>1.1</code>")
+        .expect("1.1 should be in the history");
+    let old = page
+        .find("<td>2025-01-01T00:00:00Z</td><td>release</td><td><code>1.0</code>")
+        .expect("1.0 should be in the history");
+    assert!(new < old);
+
+    let rss = f.read("site/rss.xml");
+    assert_eq!(rss.matches("<item>").count(), 2);
+    assert!(rss.contains("<link>https://example.org/versions/p/foo/</link>"));
+}
+
+#[test]
+fn site_requires_an_absolute_base_url() {
+    let f = Fixture::new();
+    f.package("foo", &[("release", "stubrelease")]);
+
+    for base_url in ["", "/versions", "example.org"] {
+        assert!(site::build(&f.ctx, &f.path("site"), base_url).is_err());
+    }
+    assert!(!f.path("site").exists());
+
+    site::build(&f.ctx, &f.path("site"), "https://example.org/versions/")
+        .expect("site should build");
+    let atom = f.read("site/atom.xml");
+    assert!(atom.contains("<id>https://example.org/versions/atom.xml</id>"));
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use color_eyre::Result;
+use common::Fixture;
+use pretty_assertions::assert_eq;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::thread;
+use std::time::{Duration, Instant};
+use vagrant::context::Context;
+use vagrant::package::engine::{Engine, Quiet};
+use vagrant::package::fetcher::Fetcher;
+use vagrant::package::{Package, PackageChannel, bulk};
+
+/// Records when each package's fetch ran
+#[derive(Default)]
+struct Recorder {
+    spans: Mutex<Vec<(String, Instant, Instant)>>,
+}
+
+impl Fetcher for Recorder {
+    fn fetch(
+        &self,
+        _ctx: &Context,
+        package: &Package,
+        _channel: &PackageChannel,
+    ) -> Result<String> {
+        let start = Instant::now();
+        thread::sleep(Duration::from_millis(30));
+        let end = Instant::now();
+        self.spans
+            .lock()
+            .expect("lock should not be poisoned")
+            .push((package.name.clone(), start, end));
+        Ok("1.0".into())
+    }
+}
+
+impl Recorder {
+    /// The most fetches that overlapped
+    fn max_overlap(&self) -> usize {
+        let spans = self
+            .spans
+            .lock()
+            .expect("lock should not be poisoned")
+            .clone();
+        spans
+            .iter()
+            .map(|(_, start, _)| {
+                spans
+                    .iter()
+                    .filter(|(_, s, e)| s <= start && start < e)
+                    .count()
+            })
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+fn fixture(hosts: &str) -> (Fixture, Vec<Package>) {
+    let mut f = Fixture::new();
+    f.write("vagrant.toml", hosts);
+    f.reload();
+
+    let packages = ["a", "b", "c", "d"]
+        .iter()
+        .map(|name| {
+            f.package_raw(
+                name,
+                &format!(
+                    "upstream = \"https://slow.example.org/{name}.git\"\n\n\
+                    [[channels]]\nname = \"release\"\nfetch = \"stubrelease\"\n"
+                ),
+            )
+        })
+        .collect();
+    (f, packages)
+}
+
+#[test]
+fn hosts_are_limited_to_their_concurrency() {
+    let (f, packages) = fixture("[hosts.\"slow.example.org\"]\nconcurrency = 1\n");
+    let recorder = Recorder::default();
+
+    let map = bulk::fetch_all(&f.ctx, &packages, &recorder).expect("fetch should succeed");
+
+    assert_eq!(map.len(), 4);
+    assert_eq!(recorder.max_overlap(), 1);
+}
+
+#[test]
+fn fetches_from_a_host_are_spaced() {
+    let (f, packages) = fixture("[hosts.\"*\"]\nspacing = \"100ms\"\n");
+    let recorder = Recorder::default();
+
+    bulk::fetch_all(&f.ctx, &packages[..2], &recorder).expect("fetch should succeed");
+
+    let mut starts = recorder
+        .spans
+        .lock()
+        .expect("lock should not be poisoned")
+        .iter()
+        .map(|(_, start, _)| *start)
+        .collect::<Vec<_>>();
+    starts.sort();
+    let (first, second) = (starts[0], starts[1]);
+    assert!(
+        second.duration_since(first) >= Duration::from_millis(100),
+        "fetches started {:?} apart",
+        second.duration_since(first)
+    );
+}
+
+#[test]
+fn capped_hosts_do_not_hold_up_others() {
+    let (f, mut packages) = fixture("[hosts.\"slow.example.org\"]\nconcurrency = 1\n");
+    packages.push(f.package_raw(
+        "e",
+        "upstream = \"https://fast.example.org/e.git\"\n\n\
+        [[channels]]\nname = \"release\"\nfetch = \"stubrelease\"\n",
+    ));
+    let recorder = Recorder::default();
+
+    // fewer workers than packages waiting on the capped host
+    let cancel = AtomicBool::new(false);
+    let engine = Engine::new(2, &Quiet, &cancel);
+    let map =
+        bulk::fetch_with(&f.ctx, &packages, &recorder, &engine).expect("fetch should succeed");
+    assert_eq!(map.len(), 5);
+    assert_eq!(recorder.max_overlap(), 2);
+
+    let spans = recorder.spans.lock().expect("lock should not be poisoned");
+    let span = |name: &str| {
+        spans
+            .iter()
+            .find(|(n, _, _)| n == name)
+            .expect("every package should be fetched")
+    };
+    assert!(span("e").1 < span("a").2, "e waited on the capped host");
+}